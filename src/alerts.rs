@@ -0,0 +1,259 @@
+use candid::{CandidType, Deserialize, Principal};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::geo::GeographicalPoint;
+
+/// Drop of the score below the threshold needed to end an alert, so that a score oscillating
+/// around the threshold does not raise one every minute
+pub const HYSTERESIS: f64 = 0.5;
+/// Upper bound on the subscriptions of a subscriber
+const MAX_SUBSCRIPTIONS: usize = 20;
+/// Events kept in the outbox of a subscriber, the oldest ones being dropped first
+const MAX_OUTBOX: usize = 200;
+
+/// Location followed by a subscriber, alerted when the score reaches the threshold
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub id: u64,
+    pub location: GeographicalPoint,
+    pub threshold: f64,
+    /// Whether the score reached the threshold and did not drop below it by more than
+    /// `HYSTERESIS` since
+    pub active: bool,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum AlertEdge {
+    /// The score reached the threshold
+    Rising,
+    /// The score dropped below the threshold by more than `HYSTERESIS`
+    Falling,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct AlertEvent {
+    /// Increasing number of the event in the outbox of the subscriber
+    pub seq: u64,
+    pub subscription: u64,
+    pub location: GeographicalPoint,
+    pub score: f64,
+    /// Time of the prediction in nanoseconds since the epoch
+    pub epoch: u64,
+    pub edge: AlertEdge,
+}
+
+pub fn validate_subscription(location: &GeographicalPoint, threshold: f64) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&location.lat) || !(-180.0..=360.0).contains(&location.lon) {
+        return Err("invalid coordinates".to_string());
+    }
+    if !(threshold > 0.0 && threshold <= 10.0) {
+        return Err(format!("threshold {threshold} is not in ]0, 10]"));
+    }
+
+    Ok(())
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq)]
+struct Subscriber {
+    subscriptions: Vec<Subscription>,
+    outbox: VecDeque<AlertEvent>,
+    next_seq: u64,
+}
+
+/// Subscriptions and outboxes of every subscriber, saved across upgrades
+#[derive(CandidType, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct AlertsState {
+    subscribers: BTreeMap<Principal, Subscriber>,
+    next_id: u64,
+}
+
+impl AlertsState {
+    /// Add a subscription, returning its id
+    pub fn subscribe(
+        &mut self,
+        subscriber: Principal,
+        location: GeographicalPoint,
+        threshold: f64,
+    ) -> Result<u64, String> {
+        validate_subscription(&location, threshold)?;
+        let entry = self.subscribers.entry(subscriber).or_default();
+        if entry.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(format!("at most {MAX_SUBSCRIPTIONS} subscriptions"));
+        }
+
+        self.next_id += 1;
+        entry.subscriptions.push(Subscription {
+            id: self.next_id,
+            location,
+            threshold,
+            active: false,
+        });
+        Ok(self.next_id)
+    }
+
+    /// Remove a subscription of the subscriber, returning whether it existed
+    pub fn unsubscribe(&mut self, subscriber: &Principal, id: u64) -> bool {
+        let Some(entry) = self.subscribers.get_mut(subscriber) else {
+            return false;
+        };
+        let len = entry.subscriptions.len();
+        entry.subscriptions.retain(|s| s.id != id);
+        let removed = entry.subscriptions.len() != len;
+        if entry.subscriptions.is_empty() && entry.outbox.is_empty() {
+            self.subscribers.remove(subscriber);
+        }
+        removed
+    }
+
+    pub fn subscriptions(&self, subscriber: &Principal) -> Vec<Subscription> {
+        self.subscribers
+            .get(subscriber)
+            .map(|s| s.subscriptions.clone())
+            .unwrap_or_default()
+    }
+
+    /// Events of the outbox of the subscriber following `after`, all of them when `None`
+    pub fn events(&self, subscriber: &Principal, after: Option<u64>) -> Vec<AlertEvent> {
+        self.subscribers
+            .get(subscriber)
+            .map(|s| {
+                s.outbox
+                    .iter()
+                    .filter(|e| after.is_none_or(|after| e.seq > after))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Drop the events of the outbox up to `seq` included, once the subscriber handled them
+    pub fn acknowledge(&mut self, subscriber: &Principal, seq: u64) {
+        if let Some(entry) = self.subscribers.get_mut(subscriber) {
+            entry.outbox.retain(|e| e.seq > seq);
+        }
+    }
+
+    /// Evaluate every subscription against a prediction made at `epoch`, `score_at` giving the
+    /// score at a location or `None` when it is not covered
+    pub fn evaluate(&mut self, epoch: u64, score_at: impl Fn(&GeographicalPoint) -> Option<f64>) {
+        for entry in self.subscribers.values_mut() {
+            for subscription in entry.subscriptions.iter_mut() {
+                let Some(score) = score_at(&subscription.location) else {
+                    continue;
+                };
+                let edge = if !subscription.active && score >= subscription.threshold {
+                    AlertEdge::Rising
+                } else if subscription.active && score < subscription.threshold - HYSTERESIS {
+                    AlertEdge::Falling
+                } else {
+                    continue;
+                };
+
+                subscription.active = edge == AlertEdge::Rising;
+                entry.next_seq += 1;
+                entry.outbox.push_back(AlertEvent {
+                    seq: entry.next_seq,
+                    subscription: subscription.id,
+                    location: subscription.location,
+                    score,
+                    epoch,
+                    edge,
+                });
+                if entry.outbox.len() > MAX_OUTBOX {
+                    entry.outbox.pop_front();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn edges(events: &[AlertEvent]) -> Vec<(u64, AlertEdge)> {
+        events.iter().map(|e| (e.epoch, e.edge)).collect()
+    }
+
+    #[test]
+    fn test_hysteresis() {
+        let mut state = AlertsState::default();
+        let tromso = GeographicalPoint::new(69.65, 18.96);
+        state.subscribe(user(1), tromso, 3.0).unwrap();
+
+        for (epoch, score) in [2.0, 3.1, 2.8, 3.0, 2.6, 2.4, 2.9, 3.2]
+            .into_iter()
+            .enumerate()
+        {
+            state.evaluate(epoch as u64, |_| Some(score));
+        }
+        // 2.8 and 2.6 are within the hysteresis, only 2.4 ends the alert
+        assert_eq!(
+            edges(&state.events(&user(1), None)),
+            vec![
+                (1, AlertEdge::Rising),
+                (5, AlertEdge::Falling),
+                (7, AlertEdge::Rising)
+            ]
+        );
+        assert!(state.subscriptions(&user(1))[0].active);
+
+        // uncovered locations keep their state
+        state.evaluate(8, |_| None);
+        assert_eq!(state.events(&user(1), None).len(), 3);
+    }
+
+    #[test]
+    fn test_outboxes() {
+        let mut state = AlertsState::default();
+        let north = GeographicalPoint::new(69.0, 19.0);
+        let south = GeographicalPoint::new(60.0, 10.0);
+        let a = state.subscribe(user(1), north, 2.0).unwrap();
+        let b = state.subscribe(user(2), south, 2.0).unwrap();
+        assert_ne!(a, b);
+
+        state.evaluate(1, |p| Some(if p.lat > 65.0 { 5.0 } else { 1.0 }));
+        let events = state.events(&user(1), None);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].subscription, a);
+        assert!(state.events(&user(2), None).is_empty());
+
+        state.evaluate(2, |_| Some(0.0));
+        assert_eq!(state.events(&user(1), Some(events[0].seq)).len(), 1);
+        state.acknowledge(&user(1), events[0].seq);
+        assert_eq!(
+            edges(&state.events(&user(1), None)),
+            vec![(2, AlertEdge::Falling)]
+        );
+
+        assert!(!state.unsubscribe(&user(1), b));
+        assert!(state.unsubscribe(&user(2), b));
+        assert!(state.subscriptions(&user(2)).is_empty());
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut state = AlertsState::default();
+        let point = GeographicalPoint::new(60.0, 10.0);
+        assert!(state.subscribe(user(1), point, 0.0).is_err());
+        assert!(state
+            .subscribe(user(1), GeographicalPoint::new(91.0, 0.0), 3.0)
+            .is_err());
+
+        for _ in 0..MAX_SUBSCRIPTIONS {
+            state.subscribe(user(1), point, 3.0).unwrap();
+        }
+        assert!(state.subscribe(user(1), point, 3.0).is_err());
+
+        for epoch in 0..MAX_OUTBOX as u64 {
+            state.evaluate(epoch, |_| Some(if epoch % 2 == 0 { 5.0 } else { 0.0 }));
+        }
+        let events = state.events(&user(1), None);
+        assert_eq!(events.len(), MAX_OUTBOX);
+        assert_eq!(events.last().unwrap().epoch, MAX_OUTBOX as u64 - 1);
+    }
+}
@@ -0,0 +1,212 @@
+use candid::{CandidType, Deserialize, Encode};
+use data_encoding::BASE64;
+use ic_certified_map::{fork, labeled, AsHashTree, Hash, HashTree, RbTree};
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+
+use crate::{http::HeaderField, ScoresMetadata};
+
+/// Label of the leaf holding `sha256` of the scores encoded as little-endian `u16`
+pub const SCORES_LABEL: &str = "scores";
+/// Label of the leaf holding `sha256` of the candid encoded `ScoresMetadata`
+pub const METADATA_LABEL: &str = "metadata";
+
+/// Label of the subtree holding `sha256` of the bodies of the certified http responses keyed by
+/// their path, as expected by the HTTP gateway (response verification v1)
+const HTTP_ASSETS_LABEL: &[u8] = b"http_assets";
+
+/// Tree whose root hash is set as the certified data of the canister, the `http_assets` subtree
+/// forking off the `scores` and `metadata` leaves (labels sorting in that order)
+#[derive(Default)]
+pub struct CertifiedTree {
+    /// `scores` and `metadata` leaves, empty before the first prediction
+    data: RbTree<&'static str, Hash>,
+    assets: RbTree<String, Hash>,
+}
+
+impl CertifiedTree {
+    pub const fn new() -> Self {
+        CertifiedTree {
+            data: RbTree::new(),
+            assets: RbTree::new(),
+        }
+    }
+
+    /// Certify the body of the response served at `path`
+    pub fn certify_response(&mut self, path: &str, body: &[u8]) {
+        self.assets
+            .insert(path.to_string(), Sha256::digest(body).into());
+    }
+
+    /// Tree of the whole map given the views of its two parts
+    fn tree<'a>(assets: HashTree<'a>, data: HashTree<'a>) -> HashTree<'a> {
+        fork(labeled(HTTP_ASSETS_LABEL, assets), data)
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        Self::tree(self.assets.as_hash_tree(), self.data.as_hash_tree()).reconstruct()
+    }
+
+    /// `IC-Certificate` header of the response served at `path` for the HTTP gateway, made of the
+    /// certificate of the canister and a witness revealing the hash of the body only
+    pub fn http_header(&self, path: &str, certificate: &[u8]) -> HeaderField {
+        let tree = Self::tree(
+            self.assets.witness(path.as_bytes()),
+            HashTree::Pruned(self.data.root_hash()),
+        );
+        (
+            "IC-Certificate".to_string(),
+            format!(
+                "certificate=:{}:, tree=:{}:",
+                BASE64.encode(certificate),
+                BASE64.encode(&cbor(&tree))
+            ),
+        )
+    }
+}
+
+/// Latest scores along with what a client needs to verify they went through consensus.
+///
+/// The certificate is verified against the IC root key and its `certified_data` must equal the
+/// root hash of `witness` (a CBOR encoded hash tree), whose `scores` and `metadata` leaves are
+/// the `sha256` of the returned data.
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct CertifiedScores {
+    pub scores: Vec<u16>,
+    pub metadata: ScoresMetadata,
+    pub certificate: ByteBuf,
+    pub witness: ByteBuf,
+}
+
+/// Hashes the scores laid out as little-endian `u16`, as they would be in a `Uint16Array`
+pub fn hash_scores(scores: &[u16]) -> Hash {
+    let mut hasher = Sha256::new();
+    for score in scores {
+        hasher.update(score.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+/// Hashes the candid encoding of the metadata
+pub fn hash_metadata(metadata: &ScoresMetadata) -> Hash {
+    let bytes = Encode!(metadata).expect("Failed to encode scores metadata");
+    Sha256::digest(bytes).into()
+}
+
+/// Builds the tree certifying both the scores and their metadata, the http responses being
+/// certified next with `CertifiedTree::certify_response`
+pub fn certified_tree(scores: &[u16], metadata: &ScoresMetadata) -> CertifiedTree {
+    let mut tree = CertifiedTree::new();
+    tree.data.insert(SCORES_LABEL, hash_scores(scores));
+    tree.data.insert(METADATA_LABEL, hash_metadata(metadata));
+    tree
+}
+
+/// CBOR encoding with the self-describe tag
+fn cbor(tree: &HashTree) -> Vec<u8> {
+    let mut serializer = serde_cbor::Serializer::new(vec![]);
+    serializer.self_describe().unwrap();
+    tree.serialize(&mut serializer)
+        .expect("Failed to serialize hash tree");
+    serializer.into_inner()
+}
+
+/// CBOR encoding of the witness revealing the `scores` and `metadata` leaves, the http responses
+/// being pruned
+pub fn witness(tree: &CertifiedTree) -> Vec<u8> {
+    cbor(&CertifiedTree::tree(
+        HashTree::Pruned(tree.assets.root_hash()),
+        tree.data.as_hash_tree(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        geo::{GridDefinition, Hemisphere},
+        ScoresGrid,
+    };
+
+    fn metadata() -> ScoresMetadata {
+        ScoresMetadata {
+            timestamp: 1,
+            grids: vec![ScoresGrid {
+                hemisphere: Hemisphere::North,
+                grid: GridDefinition {
+                    lat_start: 45.0,
+                    lat_end: 85.0,
+                    lat_steps: 2,
+                    lon_start: 0.0,
+                    lon_end: 10.0,
+                    lon_steps: 2,
+                },
+            }],
+            model_version: "0.0.0".to_string(),
+            has_derivative: false,
+        }
+    }
+
+    #[test]
+    fn test_hash_scores_little_endian() {
+        let expected: Hash = Sha256::digest([0x01, 0x00, 0x00, 0x01]).into();
+        assert_eq!(hash_scores(&[1, 256]), expected);
+    }
+
+    #[test]
+    fn test_tree_root_matches_witness() {
+        let tree = certified_tree(&[1, 2, 3, 4], &metadata());
+        let view = CertifiedTree::tree(
+            HashTree::Pruned(tree.assets.root_hash()),
+            tree.data.as_hash_tree(),
+        );
+        assert_eq!(tree.root_hash(), view.reconstruct());
+        assert_eq!(
+            tree.data.get(SCORES_LABEL.as_bytes()),
+            Some(&hash_scores(&[1, 2, 3, 4]))
+        );
+
+        let other = certified_tree(&[1, 2, 3, 5], &metadata());
+        assert_ne!(tree.root_hash(), other.root_hash());
+    }
+
+    #[test]
+    fn test_witness_is_self_described_cbor() {
+        let tree = certified_tree(&[1, 2, 3, 4], &metadata());
+        let witness = witness(&tree);
+        assert_eq!(&witness[..3], &[0xd9, 0xd9, 0xf7]);
+
+        let value: serde_cbor::Value = serde_cbor::from_slice(&witness).unwrap();
+        assert!(matches!(value, serde_cbor::Value::Array(_)));
+    }
+
+    #[test]
+    fn test_http_witness() {
+        let mut tree = certified_tree(&[1, 2, 3, 4], &metadata());
+        let root = tree.root_hash();
+        tree.certify_response("/scores.bin", &[1, 0, 2, 0]);
+        tree.certify_response("/metadata", b"{}");
+        assert_ne!(tree.root_hash(), root);
+
+        let (name, value) = tree.http_header("/scores.bin", &[9; 4]);
+        assert_eq!(name, "IC-Certificate");
+        let (certificate, witness) = value.split_once(", ").unwrap();
+        assert_eq!(certificate, "certificate=:CQkJCQ==:");
+
+        let witness = witness
+            .strip_prefix("tree=:")
+            .and_then(|w| w.strip_suffix(':'))
+            .unwrap();
+        let bytes = BASE64.decode(witness.as_bytes()).unwrap();
+        let witness: HashTree = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(witness.reconstruct(), tree.root_hash());
+
+        // the scores witness verifies against the same root
+        let scores = CertifiedTree::tree(
+            HashTree::Pruned(tree.assets.root_hash()),
+            tree.data.as_hash_tree(),
+        );
+        assert_eq!(scores.reconstruct(), tree.root_hash());
+    }
+}
@@ -0,0 +1,288 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{
+    composite,
+    format::ScoresContainer,
+    geo::{GeographicalPoint, GridDefinition},
+    overlays::{decode_score, encode_score, DERIVATIVE_DRIVEN, DERIVATIVE_RISING},
+};
+
+const NANOS_PER_HOUR: u64 = 3_600 * 1_000_000_000;
+/// Age in nanoseconds up to which the cloud cover is fully trusted, the age being the distance to
+/// the time the forecast is valid for, before or after it
+pub const CLOUDS_FRESH_AGE: u64 = 3 * NANOS_PER_HOUR;
+/// Age in nanoseconds from which the cloud cover is ignored, its weight decreasing linearly from
+/// `CLOUDS_FRESH_AGE` on
+pub const CLOUDS_MAX_AGE: u64 = 12 * NANOS_PER_HOUR;
+/// Upper bound on the points of an uploaded grid
+const MAX_CLOUD_POINTS: usize = 200_000;
+
+/// Gridded cloud cover forecast of an external provider
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct CloudCover {
+    /// Grid of the provider, independent of the prediction grids
+    pub grid: GridDefinition,
+    /// Time the forecast is valid for in nanoseconds since the epoch
+    pub timestamp: u64,
+    /// Fraction of the sky covered in [0, 1], ordered as the points of the grid
+    pub fractions: Vec<f64>,
+}
+
+impl CloudCover {
+    pub fn validate(&self) -> Result<(), String> {
+        let grid = &self.grid;
+        if grid.lat_steps < 2 || grid.lon_steps < 2 {
+            return Err("the grid needs at least 2 steps in each direction".to_string());
+        }
+        if grid.lat_start >= grid.lat_end || grid.lon_start >= grid.lon_end {
+            return Err("the grid bounds must be increasing".to_string());
+        }
+        if grid.lat_start < -90.0 || grid.lat_end > 90.0 || grid.lon_end - grid.lon_start > 360.0 {
+            return Err("the grid must lie within [-90, 90] and span at most 360°".to_string());
+        }
+        if grid.size() > MAX_CLOUD_POINTS {
+            return Err(format!("the grid has more than {MAX_CLOUD_POINTS} points"));
+        }
+        if self.fractions.len() != grid.size() {
+            return Err(format!(
+                "{} fractions given for a grid of {} points",
+                self.fractions.len(),
+                grid.size()
+            ));
+        }
+        if self.fractions.iter().any(|f| !(0.0..=1.0).contains(f)) {
+            return Err("fractions must be within [0, 1]".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Cloud fraction at the point, `None` outside of the grid. The surrounding grid points are
+    /// weighted by the inverse of their squared great-circle distance to the point, so that cells
+    /// narrowing towards the poles are not stretched as they are in latitude and longitude.
+    pub fn fraction_at(&self, point: &GeographicalPoint) -> Option<f64> {
+        self.interpolate(&self.grid.points(), point)
+    }
+
+    /// Cloud fractions at every point, `None` outside of the grid
+    pub fn resample(&self, points: &[GeographicalPoint]) -> Vec<Option<f64>> {
+        let grid_points = self.grid.points();
+        points
+            .iter()
+            .map(|p| self.interpolate(&grid_points, p))
+            .collect()
+    }
+
+    /// See `fraction_at`, given the points of the grid
+    fn interpolate(
+        &self,
+        grid_points: &[GeographicalPoint],
+        point: &GeographicalPoint,
+    ) -> Option<f64> {
+        let corners = composite::bilinear(&self.grid, point)?;
+
+        let (mut sum, mut total) = (0.0, 0.0);
+        for (idx, _) in corners {
+            let fraction = *self.fractions.get(idx)?;
+            let distance = point.angular_distance(grid_points.get(idx)?);
+            if distance < 1e-9 {
+                return Some(fraction);
+            }
+            let w = distance.powi(-2);
+            sum += w * fraction;
+            total += w;
+        }
+        Some(sum / total)
+    }
+
+    /// Weight in [0, 1] of the cloud cover at time `now` in nanoseconds since the epoch, `1` while
+    /// fresh and `0` once stale, a forecast for a later time being as stale as an old one
+    pub fn weight(&self, now: u64) -> f64 {
+        let age = now.abs_diff(self.timestamp);
+        if age <= CLOUDS_FRESH_AGE {
+            1.0
+        } else if age >= CLOUDS_MAX_AGE {
+            0.0
+        } else {
+            (CLOUDS_MAX_AGE - age) as f64 / (CLOUDS_MAX_AGE - CLOUDS_FRESH_AGE) as f64
+        }
+    }
+}
+
+/// Chance of seeing the aurora combining the scores and the cloud cover
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct Visibility {
+    /// Scores as returned by `encode_score` faded by the cloud cover, laid out as the scores they
+    /// were computed from and keeping their derivative bits
+    pub scores: Vec<u16>,
+    /// Time the cloud cover is valid for, `None` when none was uploaded
+    pub clouds_timestamp: Option<u64>,
+    /// Weight given to the cloud cover, lowered as it gets stale
+    pub clouds_weight: f64,
+}
+
+/// Visibility of the scores of the container at time `now`, scores being kept as they are where
+/// no cloud cover is known
+pub fn visibility(
+    container: &ScoresContainer,
+    clouds: Option<&CloudCover>,
+    now: u64,
+) -> Visibility {
+    let weight = clouds.map_or(0.0, |c| c.weight(now));
+    let scores = container
+        .sections
+        .iter()
+        .flat_map(|section| {
+            let fractions = match clouds {
+                Some(clouds) if weight > 0.0 => clouds.resample(&section.grid.points()),
+                _ => vec![None; section.scores.len()],
+            };
+            section
+                .scores
+                .iter()
+                .zip(fractions)
+                .map(|(encoded, fraction)| {
+                    let decoded = decode_score(*encoded);
+                    let score = decoded.score * (1.0 - weight * fraction.unwrap_or(0.0));
+                    let bits = (encoded & (DERIVATIVE_DRIVEN | DERIVATIVE_RISING) as u16) as u8;
+                    encode_score(score, bits)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    Visibility {
+        scores,
+        clouds_timestamp: clouds.map(|c| c.timestamp),
+        clouds_weight: weight,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::ScoresSection, geo::Hemisphere, overlays::encode_score};
+    use approx::assert_relative_eq;
+
+    /// Clear sky south of 60°, overcast north of it
+    fn clouds(timestamp: u64) -> CloudCover {
+        let grid = GridDefinition {
+            lat_start: 40.0,
+            lat_end: 80.0,
+            lat_steps: 5,
+            lon_start: -180.0,
+            lon_end: 170.0,
+            lon_steps: 36,
+        };
+        let fractions = grid
+            .points()
+            .iter()
+            .map(|p| if p.lat > 60.0 { 1.0 } else { 0.0 })
+            .collect();
+        CloudCover {
+            grid,
+            timestamp,
+            fractions,
+        }
+    }
+
+    #[test]
+    fn test_fraction_at() {
+        let clouds = clouds(0);
+        assert!(clouds.validate().is_ok());
+
+        assert_relative_eq!(
+            clouds
+                .fraction_at(&GeographicalPoint::new(70.0, 20.0))
+                .unwrap(),
+            1.0
+        );
+        assert_relative_eq!(
+            clouds
+                .fraction_at(&GeographicalPoint::new(50.0, 5.0))
+                .unwrap(),
+            0.0
+        );
+        // halfway between two rows
+        let halfway = clouds
+            .fraction_at(&GeographicalPoint::new(65.0, 15.0))
+            .unwrap();
+        assert_relative_eq!(halfway, 0.5, epsilon = 0.05);
+        // across the antimeridian
+        assert_relative_eq!(
+            clouds
+                .fraction_at(&GeographicalPoint::new(75.0, 175.0))
+                .unwrap(),
+            1.0
+        );
+        assert!(clouds
+            .fraction_at(&GeographicalPoint::new(-60.0, 0.0))
+            .is_none());
+
+        let mut invalid = clouds.clone();
+        invalid.fractions[3] = 1.5;
+        assert!(invalid.validate().is_err());
+        invalid.fractions.pop();
+        assert!(invalid.validate().is_err());
+    }
+
+    #[test]
+    fn test_staleness() {
+        let clouds = clouds(10 * NANOS_PER_HOUR);
+        assert_relative_eq!(clouds.weight(10 * NANOS_PER_HOUR), 1.0);
+        assert_relative_eq!(clouds.weight(13 * NANOS_PER_HOUR), 1.0);
+        // forecasts for later on are not fresh either
+        assert_relative_eq!(clouds.weight(7 * NANOS_PER_HOUR), 1.0);
+        assert_relative_eq!(
+            clouds.weight(2 * NANOS_PER_HOUR + 30 * 60 * 1_000_000_000),
+            0.5
+        );
+        // a forecast 10 hours ahead weighs as much as a 10 hours old one
+        assert_relative_eq!(clouds.weight(0), clouds.weight(20 * NANOS_PER_HOUR));
+        assert_relative_eq!(clouds.weight(0), 2.0 / 9.0);
+        assert_relative_eq!(
+            clouds.weight(17 * NANOS_PER_HOUR + 30 * 60 * 1_000_000_000),
+            0.5
+        );
+        assert_relative_eq!(clouds.weight(30 * NANOS_PER_HOUR), 0.0);
+    }
+
+    #[test]
+    fn test_visibility() {
+        let grid = GridDefinition {
+            lat_start: 50.0,
+            lat_end: 70.0,
+            lat_steps: 3,
+            lon_start: 0.0,
+            lon_end: 10.0,
+            lon_steps: 2,
+        };
+        let scores = vec![encode_score(8.0, DERIVATIVE_DRIVEN); grid.size()];
+        let container = ScoresContainer::new(
+            0,
+            false,
+            vec![ScoresSection {
+                hemisphere: Hemisphere::North,
+                grid,
+                scores: scores.clone(),
+            }],
+        );
+
+        let clear = visibility(&container, None, 0);
+        assert_eq!(clear.scores, scores);
+        assert_eq!(clear.clouds_timestamp, None);
+
+        let now = 6 * NANOS_PER_HOUR;
+        let clouded = visibility(&container, Some(&clouds(now)), now);
+        let decoded: Vec<_> = clouded.scores.iter().map(|s| decode_score(*s)).collect();
+        assert_relative_eq!(decoded[0].score, 8.0);
+        assert_relative_eq!(decoded[5].score, 0.0);
+        assert!(decoded[5].derivative_driven);
+        assert_eq!(clouded.clouds_timestamp, Some(now));
+
+        // stale clouds are ignored
+        let stale = visibility(&container, Some(&clouds(0)), CLOUDS_MAX_AGE);
+        assert_eq!(stale.scores, scores);
+        assert_relative_eq!(stale.clouds_weight, 0.0);
+    }
+}
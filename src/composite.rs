@@ -0,0 +1,375 @@
+use crate::{
+    geo::{GeographicalPoint, GridDefinition, Hemisphere},
+    model::PredictionVector,
+    overlays::ScoreVector,
+    HemispherePredictions, PredictionStorage,
+};
+
+/// Area of a cell of the grid in squared degrees, the smaller the finer
+fn cell_area(grid: &GridDefinition) -> f64 {
+    let d_lat = (grid.lat_end - grid.lat_start) / (grid.lat_steps - 1) as f64;
+    let d_lon = (grid.lon_end - grid.lon_start) / (grid.lon_steps - 1) as f64;
+    d_lat * d_lon
+}
+
+/// Indices of the four grid points surrounding `point` along with their bilinear weights, `None`
+/// when the point lies outside of the grid. Longitudes are compared modulo 360°, grids going
+/// around the globe are interpolated across the gap between their last and first longitudes.
+pub fn bilinear(grid: &GridDefinition, point: &GeographicalPoint) -> Option<[(usize, f64); 4]> {
+    // tolerance for points lying on the edges of the grid
+    const EPSILON: f64 = 1e-9;
+    if grid.lat_steps < 2 || grid.lon_steps < 2 {
+        return None;
+    }
+
+    let lat_cells = (grid.lat_steps - 1) as f64;
+    let lon_cells = (grid.lon_steps - 1) as f64;
+    let lon = grid.lon_start + (point.lon - grid.lon_start).rem_euclid(360.0);
+
+    let f = (point.lat - grid.lat_start) / (grid.lat_end - grid.lat_start) * lat_cells;
+    let g = (lon - grid.lon_start) / (grid.lon_end - grid.lon_start) * lon_cells;
+    if !(-EPSILON..=lat_cells + EPSILON).contains(&f) {
+        return None;
+    }
+    let f = f.clamp(0.0, lat_cells);
+    let i = (f.floor() as usize).min(grid.lat_steps as usize - 2);
+    let t = f - i as f64;
+
+    let (j, next_j, u) = if g <= lon_cells + EPSILON {
+        let g = g.max(0.0).min(lon_cells);
+        let j = (g.floor() as usize).min(grid.lon_steps as usize - 2);
+        (j, j + 1, g - j as f64)
+    } else {
+        let gap = grid.lon_start + 360.0 - grid.lon_end;
+        if gap > (grid.lon_end - grid.lon_start) / lon_cells + EPSILON {
+            return None;
+        }
+        let last = grid.lon_steps as usize - 1;
+        (last, 0, (lon - grid.lon_end) / gap)
+    };
+    let idx = |i: usize, j: usize| i * grid.lon_steps as usize + j;
+
+    Some([
+        (idx(i, j), (1.0 - t) * (1.0 - u)),
+        (idx(i, next_j), (1.0 - t) * u),
+        (idx(i + 1, j), t * (1.0 - u)),
+        (idx(i + 1, next_j), t * u),
+    ])
+}
+
+/// Predictions of a single kind (absolute or derivative) of a model on its grid
+struct Layer<'a> {
+    grid: GridDefinition,
+    raw: &'a [PredictionVector],
+    scores: &'a [ScoreVector],
+    std: Option<&'a [f64]>,
+}
+
+impl Layer<'_> {
+    fn sample(&self, point: &GeographicalPoint) -> Option<(PredictionVector, f64, Option<f64>)> {
+        let weights = bilinear(&self.grid, point)?;
+        let mut pv = PredictionVector {
+            lon: point.lon,
+            lat: point.lat,
+            i: 0.0,
+            j: 0.0,
+            k: 0.0,
+        };
+        let mut score = 0.0;
+        let mut std = self.std.map(|_| 0.0);
+        for (idx, w) in weights {
+            let (raw, s) = (self.raw.get(idx)?, self.scores.get(idx)?);
+            pv.i += w * raw.i;
+            pv.j += w * raw.j;
+            pv.k += w * raw.k;
+            score += w * s.score;
+            if let (Some(std), Some(layer)) = (std.as_mut(), self.std) {
+                *std += w * layer.get(idx)?;
+            }
+        }
+
+        Some((pv, score, std))
+    }
+}
+
+/// Merged predictions, scores and standard deviations of the layers
+type Merged = (Vec<PredictionVector>, Vec<ScoreVector>, Option<Vec<f64>>);
+
+/// Merge the layers onto `target`, each point being taken from the finest layer covering it.
+/// Points covered by no layer are left at zero, with an infinite standard deviation. Standard
+/// deviations are only merged when every layer taken has them.
+fn merge(target: &GridDefinition, mut layers: Vec<Layer>) -> Option<Merged> {
+    if layers.is_empty() {
+        return None;
+    }
+    layers.sort_by(|a, b| cell_area(&a.grid).total_cmp(&cell_area(&b.grid)));
+    // the finest layer being on the target grid itself, it is copied as is
+    if layers[0].grid == *target {
+        return Some((
+            layers[0].raw.to_vec(),
+            layers[0].scores.to_vec(),
+            layers[0].std.map(<[f64]>::to_vec),
+        ));
+    }
+
+    let mut std = Some(Vec::with_capacity(target.size()));
+    let (raw, scores) = target
+        .points()
+        .iter()
+        .map(|point| {
+            let (pv, score, point_std) = layers.iter().find_map(|l| l.sample(point)).unwrap_or((
+                PredictionVector {
+                    lon: point.lon,
+                    lat: point.lat,
+                    i: 0.0,
+                    j: 0.0,
+                    k: 0.0,
+                },
+                0.0,
+                Some(f64::INFINITY),
+            ));
+            match (std.as_mut(), point_std) {
+                (Some(std), Some(point_std)) => std.push(point_std),
+                _ => std = None,
+            }
+            let score = ScoreVector {
+                lat: point.lat,
+                lon: point.lon,
+                score,
+            };
+            (pv, score)
+        })
+        .unzip();
+
+    Some((raw, scores, std))
+}
+
+/// Composite map of the predictions of several models, laid on one grid per hemisphere.
+///
+/// Absolute and derivative predictions are merged separately so the composite is combined and
+/// encoded like the predictions of any model.
+pub fn composite(
+    targets: &[(Hemisphere, GridDefinition)],
+    models: &[&PredictionStorage],
+) -> PredictionStorage {
+    let mut storage = PredictionStorage::empty();
+    for (hemisphere, grid) in targets {
+        let sources: Vec<&HemispherePredictions> = models
+            .iter()
+            .filter_map(|m| m.hemispheres.get(hemisphere))
+            .collect();
+        let layers = |is_derivative: bool| -> Vec<Layer> {
+            sources
+                .iter()
+                .filter_map(|h| {
+                    let (raw, scores) = if is_derivative {
+                        (h.drv_raw.as_ref()?, h.drv.as_ref()?)
+                    } else {
+                        (h.abs_raw.as_ref()?, h.abs.as_ref()?)
+                    };
+                    Some(Layer {
+                        grid: h.grid,
+                        raw,
+                        scores,
+                        std: h.std.as_deref(),
+                    })
+                })
+                .collect()
+        };
+
+        let mut predictions = HemispherePredictions::new(*grid);
+        if let Some((raw, scores, std)) = merge(grid, layers(false)) {
+            predictions.abs_raw = Some(raw);
+            predictions.abs = Some(scores);
+            predictions.std = std;
+        }
+        if let Some((raw, scores, std)) = merge(grid, layers(true)) {
+            predictions.drv_raw = Some(raw);
+            predictions.drv = Some(scores);
+            predictions.std = predictions.std.or(std);
+        }
+        predictions.stations = sources
+            .iter()
+            .flat_map(|h| h.stations.iter().copied())
+            .collect();
+        if predictions.abs.is_some() || predictions.drv.is_some() {
+            storage.hemispheres.insert(*hemisphere, predictions);
+        }
+    }
+
+    storage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn grid(lat: (f64, f64), lon: (f64, f64), steps: u32) -> GridDefinition {
+        GridDefinition {
+            lat_start: lat.0,
+            lat_end: lat.1,
+            lat_steps: steps,
+            lon_start: lon.0,
+            lon_end: lon.1,
+            lon_steps: steps,
+        }
+    }
+
+    /// Predictions whose `i` component and score are both the latitude of the point
+    fn predictions(grid: GridDefinition) -> HemispherePredictions {
+        let points = grid.points();
+        let mut predictions = HemispherePredictions::new(grid);
+        predictions.abs_raw = Some(
+            points
+                .iter()
+                .map(|p| PredictionVector {
+                    lon: p.lon,
+                    lat: p.lat,
+                    i: p.lat,
+                    j: 0.0,
+                    k: 0.0,
+                })
+                .collect(),
+        );
+        predictions.abs = Some(
+            points
+                .iter()
+                .map(|p| ScoreVector {
+                    lat: p.lat,
+                    lon: p.lon,
+                    score: p.lat,
+                })
+                .collect(),
+        );
+        predictions
+    }
+
+    fn storage(predictions: HemispherePredictions) -> PredictionStorage {
+        let mut storage = PredictionStorage::empty();
+        storage.hemispheres.insert(Hemisphere::North, predictions);
+        storage
+    }
+
+    #[test]
+    fn test_bilinear() {
+        let grid = grid((50.0, 60.0), (0.0, 10.0), 3);
+
+        let weights = bilinear(&grid, &GeographicalPoint::new(52.5, 2.5)).unwrap();
+        for (_, w) in weights {
+            assert_relative_eq!(w, 0.25);
+        }
+        assert_eq!(weights.map(|(idx, _)| idx), [0, 1, 3, 4]);
+
+        // the last row and column are reachable
+        let weights = bilinear(&grid, &GeographicalPoint::new(60.0, 10.0)).unwrap();
+        assert_eq!(weights[3], (8, 1.0));
+
+        assert!(bilinear(&grid, &GeographicalPoint::new(61.0, 5.0)).is_none());
+        assert!(bilinear(&grid, &GeographicalPoint::new(55.0, 11.0)).is_none());
+        assert!(bilinear(&grid, &GeographicalPoint::new(55.0, 365.0)).is_some());
+    }
+
+    #[test]
+    fn test_bilinear_around_the_globe() {
+        // no gap at the antimeridian between the last and first longitudes
+        let global = grid((50.0, 60.0), (-180.0, 170.0), 36);
+        let weights = bilinear(&global, &GeographicalPoint::new(50.0, 177.5)).unwrap();
+        assert_eq!(weights[0], (35, 0.25));
+        assert_eq!(weights[1], (0, 0.75));
+
+        // unless it is wider than the cells of the grid
+        let partial = grid((50.0, 60.0), (-180.0, 160.0), 35);
+        assert!(bilinear(&partial, &GeographicalPoint::new(50.0, 177.5)).is_none());
+    }
+
+    #[test]
+    fn test_composite_prefers_finest_model() {
+        let global = grid((45.0, 85.0), (-180.0, 180.0), 5);
+        let regional = grid((60.0, 70.0), (0.0, 20.0), 11);
+        let mut regional_predictions = predictions(regional);
+        for s in regional_predictions.abs.as_mut().unwrap() {
+            s.score = 9.0;
+        }
+
+        let models = [
+            &storage(predictions(global)),
+            &storage(regional_predictions),
+        ];
+        let composite = composite(&[(Hemisphere::North, global)], &models);
+        let north = &composite.hemispheres[&Hemisphere::North];
+        assert!(north.drv.is_none());
+
+        let points = global.points();
+        let scores = north.abs.as_ref().unwrap();
+        let raw = north.abs_raw.as_ref().unwrap();
+        for (idx, p) in points.iter().enumerate() {
+            let expected = if bilinear(&regional, p).is_some() {
+                9.0
+            } else {
+                p.lat
+            };
+            assert_relative_eq!(scores[idx].score, expected);
+            assert_relative_eq!(raw[idx].i, p.lat, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_composite_of_single_model_is_identity() {
+        let grid = grid((45.0, 85.0), (-180.0, 179.0), 4);
+        let models = [&storage(predictions(grid))];
+        let merged = composite(&[(Hemisphere::North, grid)], &models);
+
+        let expected = &models[0].hemispheres[&Hemisphere::North];
+        let north = &merged.hemispheres[&Hemisphere::North];
+        let scores: Vec<f64> = north
+            .abs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.score)
+            .collect();
+        let expected: Vec<f64> = expected
+            .abs
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|s| s.score)
+            .collect();
+        assert_eq!(scores, expected);
+
+        assert!(composite(&[(Hemisphere::South, grid)], &models)
+            .hemispheres
+            .is_empty());
+    }
+
+    #[test]
+    fn test_composite_std() {
+        let target = grid((45.0, 85.0), (-180.0, 180.0), 5);
+        let regional = grid((60.0, 70.0), (0.0, 20.0), 11);
+        let mut regional_predictions = predictions(regional);
+        regional_predictions.std = Some(vec![5.0; regional.size()]);
+        regional_predictions.stations = vec![GeographicalPoint::new(65.0, 10.0)];
+
+        let models = [&storage(regional_predictions.clone())];
+        let merged = composite(&[(Hemisphere::North, target)], &models);
+        let north = &merged.hemispheres[&Hemisphere::North];
+        assert_eq!(north.stations.len(), 1);
+        let std = north.std.as_ref().unwrap();
+        for (idx, p) in target.points().iter().enumerate() {
+            if bilinear(&regional, p).is_some() {
+                assert_relative_eq!(std[idx], 5.0);
+            } else {
+                assert_eq!(std[idx], f64::INFINITY);
+            }
+        }
+
+        // unknown as soon as a layer does not know it
+        let models = [
+            &storage(regional_predictions),
+            &storage(predictions(target)),
+        ];
+        let merged = composite(&[(Hemisphere::North, target)], &models);
+        assert!(merged.hemispheres[&Hemisphere::North].std.is_none());
+    }
+}
@@ -0,0 +1,516 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+use crate::{
+    geo::{GeographicalPoint, GridDefinition, Hemisphere},
+    transfer::TransferStorage,
+};
+
+/// Upper bound on the number of SEC poles of a hemisphere, the SVD of the observation transfer
+/// matrix does not fit in a single call above it
+const MAX_SEC_POINTS: usize = 5_000;
+/// Upper bound on the number of prediction points of a hemisphere
+const MAX_PRED_POINTS: usize = 20_000;
+/// The prediction transfer matrix of a hemisphere is `pred points * 3 * sec points` floats kept in
+/// heap memory, the matrices of every hemisphere of every model take about 768 MB at this bound
+/// (the default northern grids use 288 MB) when stored dense in double precision, half of it in
+/// single precision and less once pruned, see `TransferStorage`
+const MAX_T_PRED_ELEMENTS: usize = 96_000_000;
+/// Altitudes are given in meters and must stay under the magnetosphere
+const MAX_ALTITUDE: f64 = 1_000e3;
+/// Standard deviation in nT of the noise of the magnetometers without a level of their own
+pub const DEFAULT_NOISE: f64 = 10.0;
+/// Standard deviation in nT of the field the observations do not constrain
+pub const DEFAULT_FIELD_SIGMA: f64 = 100.0;
+/// Upper bound on the stations given a noise level
+const MAX_NOISE_STATIONS: usize = 500;
+/// Angular distance in degrees within which an observation is made by a station given a noise
+/// level
+const STATION_MATCH_DISTANCE: f64 = 0.1;
+
+/// Grids of the model of a hemisphere
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HemisphereGrids {
+    /// Grid of the SEC poles
+    pub sec_grid: GridDefinition,
+    /// Grid on which predictions and scores are computed
+    pub pred_grid: GridDefinition,
+}
+
+impl HemisphereGrids {
+    /// Grids over 45–85°N, SEC poles are restricted to the longitudes covered by magnetometers
+    pub fn north() -> Self {
+        HemisphereGrids {
+            sec_grid: GridDefinition {
+                lat_start: 45.0,
+                lat_end: 85.0,
+                lat_steps: 50,
+                lon_start: -170.0,
+                lon_end: 35.0,
+                lon_steps: 50,
+            },
+            pred_grid: GridDefinition {
+                lat_start: 45.0,
+                lat_end: 85.0,
+                lat_steps: 37,
+                lon_start: -180.0,
+                lon_end: 179.0,
+                lon_steps: 130,
+            },
+        }
+    }
+
+    /// Grids over 85–45°S, southern stations are spread over every longitude
+    pub fn south() -> Self {
+        HemisphereGrids {
+            sec_grid: GridDefinition {
+                lat_start: -85.0,
+                lat_end: -45.0,
+                lat_steps: 50,
+                lon_start: -180.0,
+                lon_end: 179.0,
+                lon_steps: 50,
+            },
+            pred_grid: GridDefinition {
+                lat_start: -85.0,
+                lat_end: -45.0,
+                lat_steps: 37,
+                lon_start: -180.0,
+                lon_end: 179.0,
+                lon_steps: 130,
+            },
+        }
+    }
+
+    /// Number of elements of the prediction transfer matrix built from these grids
+    fn t_pred_elements(&self) -> usize {
+        self.pred_grid.size() * 3 * self.sec_grid.size()
+    }
+}
+
+/// Noise level of a magnetometer
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct StationNoise {
+    pub lat: f64,
+    pub lon: f64,
+    /// Standard deviation of the noise of every component in nT
+    pub sigma: f64,
+}
+
+/// Priors the uncertainty of the predictions is estimated from
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct UncertaintyConfig {
+    /// Standard deviation in nT of the noise of the stations that are not listed
+    pub default_sigma: f64,
+    pub stations: Vec<StationNoise>,
+    /// Standard deviation in nT of the field, the part of it the observations do not resolve
+    /// being unknown
+    pub field_sigma: f64,
+}
+
+impl Default for UncertaintyConfig {
+    fn default() -> Self {
+        UncertaintyConfig {
+            default_sigma: DEFAULT_NOISE,
+            stations: vec![],
+            field_sigma: DEFAULT_FIELD_SIGMA,
+        }
+    }
+}
+
+impl UncertaintyConfig {
+    fn validate(&self) -> Result<(), String> {
+        if self.stations.len() > MAX_NOISE_STATIONS {
+            return Err(format!(
+                "uncertainty: more than {MAX_NOISE_STATIONS} stations"
+            ));
+        }
+        let sigmas = [self.default_sigma, self.field_sigma]
+            .into_iter()
+            .chain(self.stations.iter().map(|s| s.sigma));
+        if sigmas
+            .into_iter()
+            .any(|sigma| !(sigma.is_finite() && sigma > 0.0))
+        {
+            return Err("uncertainty: standard deviations must be positive".to_string());
+        }
+        if self
+            .stations
+            .iter()
+            .any(|s| !(-90.0..=90.0).contains(&s.lat) || !s.lon.is_finite())
+        {
+            return Err("uncertainty: invalid station coordinates".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Standard deviation of the noise of the observations made at each location, that of the
+    /// closest station within `STATION_MATCH_DISTANCE`
+    pub fn sigmas(&self, locations: &[GeographicalPoint]) -> Vec<f64> {
+        locations
+            .iter()
+            .map(|location| {
+                self.stations
+                    .iter()
+                    .map(|s| {
+                        (
+                            location.angular_distance(&GeographicalPoint::new(s.lat, s.lon)),
+                            s,
+                        )
+                    })
+                    .filter(|(distance, _)| *distance <= STATION_MATCH_DISTANCE)
+                    .min_by(|a, b| a.0.total_cmp(&b.0))
+                    .map_or(self.default_sigma, |(_, s)| s.sigma)
+            })
+            .collect()
+    }
+}
+
+/// Parameters of the model, settable at runtime by admins
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ModelConfig {
+    /// Grids of the northern hemisphere
+    pub north: HemisphereGrids,
+    /// Grids of the southern hemisphere, only the northern hemisphere is modelled when `None`
+    pub south: Option<HemisphereGrids>,
+    /// Altitude of the SEC poles in meters
+    pub sec_altitude: f64,
+    /// Altitude of the prediction locations in meters
+    pub pred_altitude: f64,
+    /// Relative cutoff for singular values when fitting (range: 0.01-0.1)
+    pub epsilon: f64,
+    /// Priors of the uncertainty of the predictions, the defaults of `UncertaintyConfig` when
+    /// `None`
+    pub uncertainty: Option<UncertaintyConfig>,
+    /// Storage of the prediction transfer matrices, dense in double precision when `None`
+    pub t_pred_storage: Option<TransferStorage>,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        ModelConfig {
+            north: HemisphereGrids::north(),
+            south: None,
+            sec_altitude: 110e3,
+            pred_altitude: 110e3,
+            epsilon: 0.1,
+            uncertainty: None,
+            t_pred_storage: None,
+        }
+    }
+}
+
+/// Longest accepted model id
+const MAX_MODEL_ID_LEN: usize = 64;
+
+/// Configuration of a named model
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ModelDefinition {
+    /// Id given to endpoints to address the model, for example `fennoscandia`
+    pub id: String,
+    pub config: ModelConfig,
+}
+
+/// Model ids are made of lowercase ascii letters, digits and dashes
+pub fn validate_model_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || id.len() > MAX_MODEL_ID_LEN {
+        return Err(format!(
+            "model id: must be 1 to {MAX_MODEL_ID_LEN} characters long"
+        ));
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    {
+        return Err("model id: only lowercase letters, digits and dashes are allowed".to_string());
+    }
+
+    Ok(())
+}
+
+fn validate_grid(
+    name: &str,
+    grid: &GridDefinition,
+    hemisphere: Hemisphere,
+    max_points: usize,
+) -> Result<(), String> {
+    let bounds = [grid.lat_start, grid.lat_end, grid.lon_start, grid.lon_end];
+    if bounds.iter().any(|b| !b.is_finite()) {
+        return Err(format!("{name}: bounds must be finite"));
+    }
+    if !hemisphere.contains(grid.lat_start) || !hemisphere.contains(grid.lat_end) {
+        return Err(format!(
+            "{name}: latitudes must lie in the {hemisphere:?} hemisphere"
+        ));
+    }
+    if grid.lon_start < -180.0 || grid.lon_end > 360.0 {
+        return Err(format!("{name}: longitudes must be within -180..360"));
+    }
+    if grid.lat_start >= grid.lat_end || grid.lon_start >= grid.lon_end {
+        return Err(format!("{name}: ranges must be strictly increasing"));
+    }
+    if grid.lat_steps < 2 || grid.lon_steps < 2 {
+        return Err(format!("{name}: at least 2 steps are needed on each axis"));
+    }
+    if grid.size() > max_points {
+        return Err(format!("{name}: more than {max_points} points"));
+    }
+
+    Ok(())
+}
+
+fn validate_altitude(name: &str, altitude: f64) -> Result<(), String> {
+    if !(0.0..=MAX_ALTITUDE).contains(&altitude) {
+        return Err(format!("{name}: must be within 0..{MAX_ALTITUDE} meters"));
+    }
+
+    Ok(())
+}
+
+impl ModelConfig {
+    /// Grids of the given hemisphere, `None` when it is not modelled
+    pub fn grids(&self, hemisphere: Hemisphere) -> Option<&HemisphereGrids> {
+        match hemisphere {
+            Hemisphere::North => Some(&self.north),
+            Hemisphere::South => self.south.as_ref(),
+        }
+    }
+
+    /// Modelled hemispheres, in the order their scores are concatenated
+    pub fn hemispheres(&self) -> Vec<Hemisphere> {
+        Hemisphere::ALL
+            .into_iter()
+            .filter(|h| self.grids(*h).is_some())
+            .collect()
+    }
+
+    /// Number of elements of the prediction transfer matrices of every modelled hemisphere
+    pub fn t_pred_elements(&self) -> usize {
+        self.hemispheres()
+            .into_iter()
+            .filter_map(|h| self.grids(h))
+            .map(HemisphereGrids::t_pred_elements)
+            .sum()
+    }
+
+    /// Validate the configuration, `other_t_pred_elements` being the number of elements of the
+    /// prediction transfer matrices of the other models sharing the heap memory
+    pub fn validate(&self, other_t_pred_elements: usize) -> Result<(), String> {
+        for hemisphere in self.hemispheres() {
+            let grids = self.grids(hemisphere).unwrap();
+            let prefix = format!("{hemisphere:?}").to_lowercase();
+            validate_grid(
+                &format!("{prefix}.sec_grid"),
+                &grids.sec_grid,
+                hemisphere,
+                MAX_SEC_POINTS,
+            )?;
+            validate_grid(
+                &format!("{prefix}.pred_grid"),
+                &grids.pred_grid,
+                hemisphere,
+                MAX_PRED_POINTS,
+            )?;
+        }
+        if self.t_pred_elements() + other_t_pred_elements > MAX_T_PRED_ELEMENTS {
+            return Err(format!(
+                "pred_grid: transfer matrices of all models would exceed {MAX_T_PRED_ELEMENTS} elements"
+            ));
+        }
+        validate_altitude("sec_altitude", self.sec_altitude)?;
+        validate_altitude("pred_altitude", self.pred_altitude)?;
+
+        if !(self.epsilon > 0.0 && self.epsilon < 1.0) {
+            return Err("epsilon: must be within 0..1 (exclusive)".to_string());
+        }
+        if let Some(uncertainty) = &self.uncertainty {
+            uncertainty.validate()?;
+        }
+        if let Some(storage) = &self.t_pred_storage {
+            storage.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Whether switching from `previous` changes the SECs of the hemisphere, invalidating its
+    /// cached transfer matrices and its last fit
+    pub fn invalidates_secs(&self, previous: &ModelConfig, hemisphere: Hemisphere) -> bool {
+        self.grids(hemisphere).map(|g| &g.sec_grid)
+            != previous.grids(hemisphere).map(|g| &g.sec_grid)
+            || self.sec_altitude != previous.sec_altitude
+    }
+
+    /// Whether switching from `previous` invalidates the prediction transfer matrix of the
+    /// hemisphere
+    pub fn invalidates_t_pred(&self, previous: &ModelConfig, hemisphere: Hemisphere) -> bool {
+        self.invalidates_secs(previous, hemisphere)
+            || self.grids(hemisphere).map(|g| &g.pred_grid)
+                != previous.grids(hemisphere).map(|g| &g.pred_grid)
+            || self.pred_altitude != previous.pred_altitude
+            || self.t_pred_storage.unwrap_or_default()
+                != previous.t_pred_storage.unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_valid() {
+        assert_eq!(ModelConfig::default().validate(0), Ok(()));
+        assert_eq!(
+            ModelConfig::default().hemispheres(),
+            vec![Hemisphere::North]
+        );
+
+        let config = ModelConfig {
+            south: Some(HemisphereGrids::south()),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(0), Ok(()));
+        assert_eq!(
+            config.hemispheres(),
+            vec![Hemisphere::North, Hemisphere::South]
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_values() {
+        let mut config = ModelConfig::default();
+        config.north.sec_grid.lat_end = 95.0;
+        assert!(config.validate(0).is_err());
+
+        let mut config = ModelConfig::default();
+        config.north.pred_grid.lon_start = 179.0;
+        config.north.pred_grid.lon_end = -180.0;
+        assert!(config.validate(0).is_err());
+
+        let mut config = ModelConfig::default();
+        config.north.pred_grid.lat_steps = 1_000;
+        assert!(config.validate(0).is_err());
+
+        let mut config = ModelConfig::default();
+        config.north.pred_grid.lat_steps = 100;
+        config.north.sec_grid.lat_steps = 90;
+        assert!(config.validate(0).is_err());
+
+        // grids of a hemisphere cannot cross the equator
+        let config = ModelConfig {
+            south: Some(HemisphereGrids::north()),
+            ..Default::default()
+        };
+        assert!(config.validate(0).is_err());
+
+        let config = ModelConfig {
+            sec_altitude: f64::NAN,
+            ..Default::default()
+        };
+        assert!(config.validate(0).is_err());
+
+        let config = ModelConfig {
+            epsilon: 0.0,
+            ..Default::default()
+        };
+        assert!(config.validate(0).is_err());
+    }
+
+    #[test]
+    fn test_uncertainty_config() {
+        let uncertainty = UncertaintyConfig {
+            default_sigma: 5.0,
+            stations: vec![
+                StationNoise {
+                    lat: 69.66,
+                    lon: 18.94,
+                    sigma: 2.0,
+                },
+                StationNoise {
+                    lat: 69.70,
+                    lon: 18.94,
+                    sigma: 3.0,
+                },
+            ],
+            field_sigma: 50.0,
+        };
+        let config = ModelConfig {
+            uncertainty: Some(uncertainty.clone()),
+            ..Default::default()
+        };
+        assert_eq!(config.validate(0), Ok(()));
+        assert_eq!(
+            uncertainty.sigmas(&[
+                GeographicalPoint::new(69.65, 18.95),
+                GeographicalPoint::new(60.0, 10.0)
+            ]),
+            vec![2.0, 5.0]
+        );
+
+        let config = ModelConfig {
+            uncertainty: Some(UncertaintyConfig {
+                default_sigma: 0.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(config.validate(0).is_err());
+    }
+
+    #[test]
+    fn test_validate_shares_t_pred_budget() {
+        let config = ModelConfig::default();
+        assert_eq!(config.validate(config.t_pred_elements()), Ok(()));
+        assert!(config.validate(2 * config.t_pred_elements()).is_err());
+    }
+
+    #[test]
+    fn test_validate_model_id() {
+        assert_eq!(validate_model_id("north-america"), Ok(()));
+        assert_eq!(validate_model_id("global2"), Ok(()));
+        assert!(validate_model_id("").is_err());
+        assert!(validate_model_id("Fennoscandia").is_err());
+        assert!(validate_model_id("a b").is_err());
+        assert!(validate_model_id(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn test_invalidation() {
+        let previous = ModelConfig::default();
+        let north = Hemisphere::North;
+        let south = Hemisphere::South;
+
+        let mut config = previous.clone();
+        config.epsilon = 0.05;
+        assert!(!config.invalidates_secs(&previous, north));
+        assert!(!config.invalidates_t_pred(&previous, north));
+
+        let mut config = previous.clone();
+        config.north.pred_grid.lat_steps = 40;
+        assert!(!config.invalidates_secs(&previous, north));
+        assert!(config.invalidates_t_pred(&previous, north));
+        assert!(!config.invalidates_t_pred(&previous, south));
+
+        let mut config = previous.clone();
+        config.sec_altitude = 100e3;
+        assert!(config.invalidates_secs(&previous, north));
+        assert!(config.invalidates_t_pred(&previous, north));
+
+        // the default storage given explicitly keeps the matrices
+        let mut config = previous.clone();
+        config.t_pred_storage = Some(TransferStorage::default());
+        assert!(!config.invalidates_t_pred(&previous, north));
+        config.t_pred_storage = Some(TransferStorage {
+            single_precision: true,
+            prune_below: 0.0,
+        });
+        assert!(!config.invalidates_secs(&previous, north));
+        assert!(config.invalidates_t_pred(&previous, north));
+
+        let mut config = previous.clone();
+        config.south = Some(HemisphereGrids::south());
+        assert!(!config.invalidates_secs(&previous, north));
+        assert!(config.invalidates_secs(&previous, south));
+    }
+}
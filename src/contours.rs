@@ -0,0 +1,624 @@
+use candid::{CandidType, Deserialize};
+use std::collections::BTreeMap;
+
+use crate::{
+    composite::bilinear,
+    format::ScoresContainer,
+    geo::{GeographicalPoint, GridDefinition, Hemisphere},
+    overlays::ScoreVector,
+};
+
+/// Side of the cells of the pole-centred plane the scores are resampled on, in degrees
+const CELL_SIZE: f64 = 1.0;
+/// Largest angular distance to the geomagnetic pole contoured, in degrees
+const MAX_EXTENT: f64 = 90.0;
+/// Longitude step of the edges closing a ring along the geographic pole, in degrees
+const POLE_STEP: f64 = 90.0;
+pub const MAX_THRESHOLDS: usize = 20;
+pub const DEFAULT_THRESHOLDS: [f64; 9] = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0];
+
+/// Area where the score reaches a threshold
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct Contour {
+    pub hemisphere: Hemisphere,
+    pub threshold: f64,
+    /// Polygons made of their closed exterior ring, counterclockwise, followed by their holes,
+    /// cut at the antimeridian
+    pub polygons: Vec<Vec<Vec<GeographicalPoint>>>,
+}
+
+pub fn validate_thresholds(thresholds: &[f64]) -> Result<(), String> {
+    if thresholds.is_empty() || thresholds.len() > MAX_THRESHOLDS {
+        return Err(format!(
+            "between 1 and {MAX_THRESHOLDS} thresholds expected"
+        ));
+    }
+    if let Some(t) = thresholds.iter().find(|t| !(*t > &0.0 && *t <= &10.0)) {
+        return Err(format!("threshold {t} is not in ]0, 10]"));
+    }
+
+    Ok(())
+}
+
+/// Scores resampled on a square plane centred on the geomagnetic pole of a hemisphere, the
+/// azimuthal equidistant projection around the pole. Rotating the grid this way keeps the oval
+/// in one piece where the geographic grid splits it at the antimeridian.
+///
+/// Values are laid row by row, `x` pointing east and `y` north from the pole. The outermost
+/// values are left at zero so every isoline is closed.
+struct Plane {
+    pole: GeographicalPoint,
+    /// Number of values on each side of the pole
+    half: usize,
+    values: Vec<f64>,
+}
+
+impl Plane {
+    fn new(hemisphere: Hemisphere, grid: &GridDefinition, scores: &[ScoreVector]) -> Option<Self> {
+        if scores.len() != grid.size() || scores.is_empty() {
+            return None;
+        }
+        let pole = hemisphere.geomagnetic_pole();
+        let extent = grid
+            .points()
+            .iter()
+            .map(|p| pole.angular_distance(p))
+            .fold(0.0, f64::max)
+            .min(MAX_EXTENT);
+
+        let mut plane = Plane {
+            pole,
+            half: (extent / CELL_SIZE).ceil() as usize + 1,
+            values: vec![],
+        };
+        let n = plane.side();
+        plane.values = (0..n * n)
+            .map(|idx| {
+                let (row, col) = (idx / n, idx % n);
+                if row == 0 || col == 0 || row == n - 1 || col == n - 1 {
+                    return 0.0;
+                }
+                bilinear(grid, &plane.point(col as f64, row as f64))
+                    .map(|weights| weights.iter().map(|(i, w)| w * scores[*i].score).sum())
+                    .unwrap_or(0.0)
+            })
+            .collect();
+
+        Some(plane)
+    }
+
+    fn side(&self) -> usize {
+        2 * self.half + 1
+    }
+
+    fn value(&self, row: usize, col: usize) -> f64 {
+        self.values[row * self.side() + col]
+    }
+
+    /// Geographical point at the given column and row, fractional between the values
+    fn point(&self, col: f64, row: f64) -> GeographicalPoint {
+        let x = (col - self.half as f64) * CELL_SIZE;
+        let y = (row - self.half as f64) * CELL_SIZE;
+        self.pole.destination(x.hypot(y), x.atan2(y).to_degrees())
+    }
+}
+
+/// Side of a cell crossed by an isoline: whether it is horizontal and the row and column of its
+/// first end
+type Edge = (bool, usize, usize);
+
+/// Closed isolines of the plane at `threshold` as `(column, row)` rings, keeping the values
+/// reaching the threshold on their left
+fn isolines(plane: &Plane, threshold: f64) -> Vec<Vec<(f64, f64)>> {
+    let n = plane.side();
+    let inside = |row: usize, col: usize| plane.value(row, col) >= threshold;
+
+    // marching squares, corners of the cells numbered counterclockwise from the bottom left
+    let mut next: BTreeMap<Edge, Edge> = BTreeMap::new();
+    for row in 0..n - 1 {
+        for col in 0..n - 1 {
+            let bottom = (true, row, col);
+            let right = (false, row, col + 1);
+            let top = (true, row + 1, col);
+            let left = (false, row, col);
+            let case = inside(row, col) as u8
+                | (inside(row, col + 1) as u8) << 1
+                | (inside(row + 1, col + 1) as u8) << 2
+                | (inside(row + 1, col) as u8) << 3;
+            // saddles are resolved with the mean of the corners
+            let center = (plane.value(row, col)
+                + plane.value(row, col + 1)
+                + plane.value(row + 1, col + 1)
+                + plane.value(row + 1, col))
+                / 4.0
+                >= threshold;
+
+            let segments = match case {
+                1 => vec![(bottom, left)],
+                2 => vec![(right, bottom)],
+                3 => vec![(right, left)],
+                4 => vec![(top, right)],
+                5 if center => vec![(bottom, right), (top, left)],
+                5 => vec![(bottom, left), (top, right)],
+                6 => vec![(top, bottom)],
+                7 => vec![(top, left)],
+                8 => vec![(left, top)],
+                9 => vec![(bottom, top)],
+                10 if center => vec![(left, bottom), (right, top)],
+                10 => vec![(right, bottom), (left, top)],
+                11 => vec![(right, top)],
+                12 => vec![(left, right)],
+                13 => vec![(bottom, right)],
+                14 => vec![(left, bottom)],
+                _ => vec![],
+            };
+            next.extend(segments);
+        }
+    }
+
+    let crossing = |(horizontal, row, col): Edge| {
+        let (from, to) = if horizontal {
+            (plane.value(row, col), plane.value(row, col + 1))
+        } else {
+            (plane.value(row, col), plane.value(row + 1, col))
+        };
+        let t = (threshold - from) / (to - from);
+        if horizontal {
+            (col as f64 + t, row as f64)
+        } else {
+            (col as f64, row as f64 + t)
+        }
+    };
+
+    let mut rings = vec![];
+    while let Some((&start, _)) = next.first_key_value() {
+        let mut ring = vec![crossing(start)];
+        let mut edge = start;
+        while let Some(following) = next.remove(&edge) {
+            if following == start {
+                break;
+            }
+            ring.push(crossing(following));
+            edge = following;
+        }
+        ring.push(ring[0]);
+        rings.push(ring);
+    }
+
+    rings
+}
+
+/// Twice the signed area of the ring, positive when counterclockwise
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.windows(2)
+        .map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1)
+        .sum()
+}
+
+fn contains(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    ring.windows(2)
+        .filter(|w| {
+            let ((x0, y0), (x1, y1)) = (w[0], w[1]);
+            (y0 > y) != (y1 > y) && x < x0 + (y - y0) / (y1 - y0) * (x1 - x0)
+        })
+        .count()
+        % 2
+        == 1
+}
+
+/// Groups the rings into polygons, each hole going to the smallest exterior ring holding it
+fn polygons(rings: Vec<Vec<(f64, f64)>>) -> Vec<Vec<Vec<(f64, f64)>>> {
+    let (mut exteriors, holes): (Vec<_>, Vec<_>) =
+        rings.into_iter().partition(|r| signed_area(r) > 0.0);
+    exteriors.sort_by(|a, b| signed_area(a).total_cmp(&signed_area(b)));
+
+    let mut polygons: Vec<Vec<Vec<(f64, f64)>>> = exteriors.into_iter().map(|e| vec![e]).collect();
+    for hole in holes {
+        if let Some(polygon) = polygons.iter_mut().find(|p| contains(&p[0], hole[0])) {
+            polygon.push(hole);
+        }
+    }
+
+    polygons
+}
+
+/// Ring of geographical points as `(lon, lat)` with continuous longitudes, its last point being
+/// shifted by 360° for each turn around the geographic pole
+fn unwrap(ring: &[GeographicalPoint]) -> Vec<(f64, f64)> {
+    let mut unwrapped: Vec<(f64, f64)> = Vec::with_capacity(ring.len());
+    for p in ring {
+        let lon = match unwrapped.last() {
+            Some(&(previous, _)) => previous + (p.lon - previous + 180.0).rem_euclid(360.0) - 180.0,
+            None => p.lon,
+        };
+        unwrapped.push((lon, p.lat));
+    }
+    unwrapped
+}
+
+/// Turns of an unwrapped ring around the geographic pole, positive eastward
+fn turns(ring: &[(f64, f64)]) -> i32 {
+    ((ring[ring.len() - 1].0 - ring[0].0) / 360.0).round() as i32
+}
+
+/// Close an unwrapped ring going around the geographic pole along the parallel of the pole, so
+/// that it holds the pole
+fn close_at_pole(ring: &mut Vec<(f64, f64)>, pole_lat: f64) {
+    let (from, to) = (ring[ring.len() - 1].0, ring[0].0);
+    let steps = ((from - to).abs() / POLE_STEP).ceil() as usize;
+    ring.extend((0..=steps).map(|i| (from + (to - from) * i as f64 / steps as f64, pole_lat)));
+    ring.push(ring[0]);
+}
+
+/// Join an unwrapped exterior ring and a hole both going around the geographic pole into a
+/// single ring holding the band between them, the hole being entered at its point closest in
+/// longitude to the end of the exterior
+fn bridge(exterior: &mut Vec<(f64, f64)>, hole: &[GeographicalPoint]) {
+    let end = exterior[exterior.len() - 1].0;
+    let offset = |lon: f64| (lon - end + 180.0).rem_euclid(360.0) - 180.0;
+    let points = &hole[..hole.len() - 1];
+    let start = (0..points.len())
+        .min_by(|a, b| {
+            offset(points[*a].lon)
+                .abs()
+                .total_cmp(&offset(points[*b].lon).abs())
+        })
+        .unwrap();
+    let mut rotated: Vec<_> = points[start..]
+        .iter()
+        .chain(&points[..=start])
+        .copied()
+        .collect();
+
+    let mut unwrapped = unwrap(&rotated);
+    // the hole is followed westward when the exterior goes eastward and the other way round
+    if turns(&unwrapped).signum() == turns(exterior).signum() {
+        rotated.reverse();
+        unwrapped = unwrap(&rotated);
+    }
+    let shift = end + offset(unwrapped[0].0) - unwrapped[0].0;
+    exterior.extend(unwrapped.iter().map(|(lon, lat)| (lon + shift, *lat)));
+    exterior.push(exterior[0]);
+}
+
+/// Part of a closed ring between two meridians, Sutherland-Hodgman style: pieces of the ring
+/// left apart by the clipping are joined along the meridians
+fn clip(ring: &[(f64, f64)], west: f64, east: f64) -> Vec<(f64, f64)> {
+    let half = |ring: &[(f64, f64)], edge: f64, keep: &dyn Fn(f64) -> bool| {
+        let mut clipped = vec![];
+        for w in ring.windows(2) {
+            let (a, b) = (w[0], w[1]);
+            if keep(a.0) {
+                clipped.push(a);
+            }
+            if keep(a.0) != keep(b.0) {
+                clipped.push((edge, a.1 + (edge - a.0) / (b.0 - a.0) * (b.1 - a.1)));
+            }
+        }
+        if let Some(&first) = clipped.first() {
+            clipped.push(first);
+        }
+        clipped
+    };
+
+    let clipped = half(ring, west, &|lon| lon >= west);
+    half(&clipped, east, &|lon| lon <= east)
+}
+
+/// Cut a polygon along the antimeridian as RFC 7946 recommends, the rings going around the
+/// geographic pole, at `pole_lat`, being closed along it first
+fn cut_at_antimeridian(
+    polygon: Vec<Vec<GeographicalPoint>>,
+    pole_lat: f64,
+) -> Vec<Vec<Vec<GeographicalPoint>>> {
+    let mut exterior = unwrap(&polygon[0]);
+    let mut holes: Vec<_> = polygon[1..].iter().map(|h| unwrap(h)).collect();
+    if turns(&exterior) != 0 {
+        // a hole going around the pole as well leaves a band between both rings
+        match holes.iter().position(|h| turns(h) != 0) {
+            Some(idx) => {
+                holes.remove(idx);
+                bridge(&mut exterior, &polygon[idx + 1]);
+            }
+            None => close_at_pole(&mut exterior, pole_lat),
+        }
+    }
+
+    let (west, east) = exterior
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(w, e), (lon, _)| {
+            (w.min(*lon), e.max(*lon))
+        });
+    let area = |ring: &[(f64, f64)]| signed_area(ring).abs() > 1e-9;
+    let strips = ((west + 180.0) / 360.0).floor() as i32..((east + 180.0) / 360.0).ceil() as i32;
+    strips
+        .filter_map(|strip| {
+            let shift = 360.0 * strip as f64;
+            let (strip_west, strip_east) = (shift - 180.0, shift + 180.0);
+            let mut exterior = clip(&exterior, strip_west, strip_east);
+            if !area(&exterior) {
+                return None;
+            }
+            if signed_area(&exterior) < 0.0 {
+                exterior.reverse();
+            }
+
+            // holes may sit anywhere modulo 360° along rings closed at the pole
+            let (west, east) = (west.max(strip_west), east.min(strip_east));
+            let holes = holes.iter().flat_map(|hole| {
+                let base = hole[0].0 - 360.0 * ((hole[0].0 - west) / 360.0).floor();
+                [-360.0, 0.0, 360.0].map(|turn| {
+                    let moved: Vec<_> = hole
+                        .iter()
+                        .map(|(lon, lat)| (lon - hole[0].0 + base + turn, *lat))
+                        .collect();
+                    let mut clipped = clip(&moved, west, east);
+                    if signed_area(&clipped) > 0.0 {
+                        clipped.reverse();
+                    }
+                    clipped
+                })
+            });
+
+            let rings = std::iter::once(exterior)
+                .chain(holes.filter(|h| area(h)))
+                .map(|ring| {
+                    ring.into_iter()
+                        .map(|(lon, lat)| GeographicalPoint::new(lat, lon - shift))
+                        .collect()
+                })
+                .collect();
+            Some(rings)
+        })
+        .collect()
+}
+
+/// Contours of the scores of a hemisphere at each threshold, computed on the plane centred on
+/// the geomagnetic pole (see `Plane`) and projected back to geographical coordinates.
+///
+/// Unlike `contours/index.ts` the scores themselves are contoured, with no density estimation.
+/// Polygons are then cut along the antimeridian, see `cut_at_antimeridian`.
+pub fn contours(
+    hemisphere: Hemisphere,
+    grid: &GridDefinition,
+    scores: &[ScoreVector],
+    thresholds: &[f64],
+) -> Vec<Contour> {
+    let Some(plane) = Plane::new(hemisphere, grid, scores) else {
+        return vec![];
+    };
+    let pole_lat = match hemisphere {
+        Hemisphere::North => 90.0,
+        Hemisphere::South => -90.0,
+    };
+
+    thresholds
+        .iter()
+        .map(|&threshold| Contour {
+            hemisphere,
+            threshold,
+            polygons: polygons(isolines(&plane, threshold))
+                .into_iter()
+                .flat_map(|polygon| {
+                    let polygon = polygon
+                        .into_iter()
+                        .map(|ring| ring.into_iter().map(|(c, r)| plane.point(c, r)).collect())
+                        .collect();
+                    cut_at_antimeridian(polygon, pole_lat)
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Contours of every section of the container, from the scores as they are served
+pub fn container_contours(container: &ScoresContainer, thresholds: &[f64]) -> Vec<Contour> {
+    container
+        .sections
+        .iter()
+        .flat_map(|section| {
+            contours(
+                section.hemisphere,
+                &section.grid,
+                &section.score_vectors(),
+                thresholds,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn global_grid(hemisphere: Hemisphere) -> GridDefinition {
+        let (lat_start, lat_end) = match hemisphere {
+            Hemisphere::North => (40.0, 88.0),
+            Hemisphere::South => (-88.0, -40.0),
+        };
+        GridDefinition {
+            lat_start,
+            lat_end,
+            lat_steps: 25,
+            lon_start: -180.0,
+            lon_end: 175.0,
+            lon_steps: 72,
+        }
+    }
+
+    /// Scores of a ring-shaped oval, 4 between 15° and 25° from the geomagnetic pole
+    fn oval(hemisphere: Hemisphere, grid: &GridDefinition) -> Vec<ScoreVector> {
+        let pole = hemisphere.geomagnetic_pole();
+        grid.points()
+            .iter()
+            .map(|p| {
+                let d = pole.angular_distance(p);
+                ScoreVector {
+                    lat: p.lat,
+                    lon: p.lon,
+                    score: if (15.0..=25.0).contains(&d) { 4.0 } else { 0.0 },
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_thresholds() {
+        assert!(validate_thresholds(&DEFAULT_THRESHOLDS).is_ok());
+        assert!(validate_thresholds(&[]).is_err());
+        assert!(validate_thresholds(&[0.0]).is_err());
+        assert!(validate_thresholds(&[2.0, f64::NAN]).is_err());
+        assert!(validate_thresholds(&[1.0; MAX_THRESHOLDS + 1]).is_err());
+    }
+
+    #[test]
+    fn test_isolines_of_a_square() {
+        // a single value above the threshold in the middle of the plane
+        let mut plane = Plane {
+            pole: GeographicalPoint::new(90.0, 0.0),
+            half: 2,
+            values: vec![0.0; 25],
+        };
+        plane.values[12] = 2.0;
+
+        let rings = isolines(&plane, 1.0);
+        assert_eq!(rings.len(), 1);
+        let ring = &rings[0];
+        assert_eq!(ring.len(), 5);
+        assert_eq!(ring.first(), ring.last());
+        // a diamond of half diagonal 0.5 around the value, counterclockwise
+        assert_relative_eq!(signed_area(ring), 2.0 * 0.5);
+        assert!(contains(ring, (2.0, 2.0)));
+    }
+
+    #[test]
+    fn test_polygons_with_holes() {
+        let square =
+            |from: f64, to: f64| vec![(from, from), (to, from), (to, to), (from, to), (from, from)];
+        let mut hole = square(1.0, 2.0);
+        hole.reverse();
+        let polygons = polygons(vec![hole, square(0.0, 3.0), square(5.0, 6.0)]);
+
+        assert_eq!(polygons.len(), 2);
+        assert_eq!(polygons[0].len() + polygons[1].len(), 3);
+        let with_hole = polygons.iter().find(|p| p.len() == 2).unwrap();
+        assert_eq!(with_hole[0][0], (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_contours_of_the_oval() {
+        for hemisphere in Hemisphere::ALL {
+            let grid = global_grid(hemisphere);
+            let contours = contours(hemisphere, &grid, &oval(hemisphere, &grid), &[2.0, 5.0]);
+            assert_eq!(contours.len(), 2);
+            assert!(contours[1].polygons.is_empty());
+
+            // the oval goes around the geographic pole, the band it makes is cut in two pieces
+            // at the antimeridian
+            let polygons = &contours[0].polygons;
+            assert_eq!(polygons.len(), 2, "{hemisphere:?}");
+            let pole = hemisphere.geomagnetic_pole();
+            for polygon in polygons {
+                assert_eq!(polygon.len(), 1, "{hemisphere:?}");
+                assert!(polygon[0].len() > 30);
+                for p in polygon[0].iter().filter(|p| p.lon.abs() < 180.0) {
+                    let d = pole.angular_distance(p);
+                    assert!((d - 25.0).abs() < 2.5 || (d - 15.0).abs() < 2.5, "{p:?}");
+                }
+            }
+
+            let covered = |p: GeographicalPoint| {
+                let p = (p.lon, p.lat);
+                polygons.iter().any(|polygon| {
+                    let ring: Vec<_> = polygon[0].iter().map(|p| (p.lon, p.lat)).collect();
+                    contains(&ring, p)
+                })
+            };
+            for bearing in (0..360).step_by(30) {
+                assert!(covered(pole.destination(20.0, bearing as f64)));
+                assert!(!covered(pole.destination(10.0, bearing as f64)));
+                assert!(!covered(pole.destination(30.0, bearing as f64)));
+            }
+            assert_antimeridian_cut(&contours);
+        }
+    }
+
+    /// Longitudes within [-180, 180] and no edge spanning more than 180° of them
+    fn assert_antimeridian_cut(contours: &[Contour]) {
+        for ring in contours.iter().flat_map(|c| c.polygons.iter().flatten()) {
+            assert_eq!(ring.first(), ring.last());
+            for w in ring.windows(2) {
+                assert!(w[0].lon.abs() <= 180.0);
+                assert!((w[1].lon - w[0].lon).abs() <= 180.0, "{:?}", w);
+            }
+        }
+    }
+
+    #[test]
+    fn test_contours_cut_at_the_antimeridian() {
+        let hemisphere = Hemisphere::North;
+        let grid = global_grid(hemisphere);
+        // scores of a disc with a hole, centred on the given longitude
+        let disc = |lon: f64| -> Vec<ScoreVector> {
+            let center = GeographicalPoint::new(60.0, lon);
+            let hole = GeographicalPoint::new(60.0, lon + 3.0);
+            grid.points()
+                .iter()
+                .map(|p| ScoreVector {
+                    lat: p.lat,
+                    lon: p.lon,
+                    score: if center.angular_distance(p) < 12.0 && hole.angular_distance(p) > 4.0 {
+                        4.0
+                    } else {
+                        0.0
+                    },
+                })
+                .collect()
+        };
+
+        // away from the antimeridian the polygon is left whole
+        let contours = contours(hemisphere, &grid, &disc(20.0), &[2.0]);
+        assert_antimeridian_cut(&contours);
+        assert_eq!(contours[0].polygons.len(), 1);
+        assert_eq!(contours[0].polygons[0].len(), 2);
+
+        // across it, the disc is split in a western and an eastern piece, the hole as well
+        let contours = super::contours(hemisphere, &grid, &disc(179.0), &[2.0]);
+        assert_antimeridian_cut(&contours);
+        let polygons = &contours[0].polygons;
+        assert_eq!(polygons.len(), 2);
+        for polygon in polygons {
+            assert_eq!(polygon.len(), 2);
+            assert!(polygon[0].iter().all(|p| p.lon.abs() > 150.0));
+            let rings: Vec<Vec<_>> = polygon
+                .iter()
+                .map(|r| r.iter().map(|p| (p.lon, p.lat)).collect())
+                .collect();
+            assert!(signed_area(&rings[0]) > 0.0);
+            assert!(signed_area(&rings[1]) < 0.0);
+        }
+    }
+
+    #[test]
+    fn test_container_contours() {
+        let grid = global_grid(Hemisphere::North);
+        let container = ScoresContainer::new(
+            0,
+            false,
+            vec![crate::format::ScoresSection {
+                hemisphere: Hemisphere::North,
+                grid,
+                scores: oval(Hemisphere::North, &grid)
+                    .iter()
+                    .map(|s| crate::overlays::encode_score(s.score, 0))
+                    .collect(),
+            }],
+        );
+
+        let contours = container_contours(&container, &[3.0]);
+        assert_eq!(contours.len(), 1);
+        // both halves of the oval cut at the antimeridian
+        assert_eq!(contours[0].polygons.len(), 2);
+    }
+}
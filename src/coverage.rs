@@ -0,0 +1,145 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{
+    geo::{GeographicalPoint, R_EARTH},
+    sphere::angular_distance_and_bearing,
+};
+
+/// Radius in km within which stations are counted when no coverage policy is set
+pub const DEFAULT_COVERAGE_RADIUS: f64 = 500.0;
+
+/// How well a prediction point is covered by the stations of the fit
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Coverage {
+    /// Distance in km to the nearest station, infinite without any
+    pub distance: f64,
+    /// Number of stations within the radius
+    pub stations: u32,
+}
+
+fn km_to_degrees(distance: f64) -> f64 {
+    (distance * 1e3 / R_EARTH).to_degrees()
+}
+
+fn degrees_to_km(angle: f64) -> f64 {
+    angle.to_radians() * R_EARTH / 1e3
+}
+
+/// Stations sorted by latitude, those within an angular distance of a point lying within as many
+/// degrees of latitude of it
+#[derive(Debug, Clone, Default)]
+pub struct StationIndex {
+    stations: Vec<GeographicalPoint>,
+}
+
+impl StationIndex {
+    pub fn new(stations: &[GeographicalPoint]) -> Self {
+        let mut stations = stations.to_vec();
+        stations.sort_by(|a, b| a.lat.total_cmp(&b.lat));
+        StationIndex { stations }
+    }
+
+    /// Angular distances in degrees from the point to the stations within `width` degrees of
+    /// latitude of it
+    fn band_distances(&self, point: &GeographicalPoint, width: f64) -> Vec<f64> {
+        let start = self.stations.partition_point(|s| s.lat < point.lat - width);
+        let end = self
+            .stations
+            .partition_point(|s| s.lat <= point.lat + width);
+        if start >= end {
+            return vec![];
+        }
+
+        let (theta, _) = angular_distance_and_bearing(&[*point], &self.stations[start..end]);
+        theta.iter().map(|t| t.to_degrees()).collect()
+    }
+
+    /// Coverage of the point, counting the stations within `radius` km
+    pub fn coverage(&self, point: &GeographicalPoint, radius: f64) -> Coverage {
+        let radius = km_to_degrees(radius);
+        let mut distances = self.band_distances(point, radius);
+        let stations = distances.iter().filter(|d| **d <= radius).count() as u32;
+
+        // widen the band until it holds a station as close as its width, no station out of the
+        // band being closer
+        let mut width = radius;
+        let nearest = loop {
+            let nearest = distances.iter().copied().fold(f64::INFINITY, f64::min);
+            if nearest <= width || width >= 180.0 {
+                break nearest;
+            }
+            width = (2.0 * width).max(1.0);
+            distances = self.band_distances(point, width);
+        };
+
+        Coverage {
+            distance: degrees_to_km(nearest),
+            stations,
+        }
+    }
+
+    pub fn coverages(&self, points: &[GeographicalPoint], radius: f64) -> Vec<Coverage> {
+        points.iter().map(|p| self.coverage(p, radius)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_coverage() {
+        let index = StationIndex::new(&[
+            GeographicalPoint::new(69.66, 18.94),
+            GeographicalPoint::new(60.0, 10.0),
+            GeographicalPoint::new(62.0, 10.0),
+            GeographicalPoint::new(64.0, 10.0),
+        ]);
+
+        let coverage = index.coverage(&GeographicalPoint::new(62.0, 10.0), 250.0);
+        assert_relative_eq!(coverage.distance, 0.0, epsilon = 1e-3);
+        assert_eq!(coverage.stations, 3);
+
+        // 1° of latitude is around 111 km
+        let coverage = index.coverage(&GeographicalPoint::new(59.0, 10.0), 150.0);
+        assert_relative_eq!(coverage.distance, 111.2, epsilon = 0.1);
+        assert_eq!(coverage.stations, 1);
+
+        // far from every station
+        let coverage = index.coverage(&GeographicalPoint::new(-40.0, -170.0), 500.0);
+        assert!(coverage.distance > 10_000.0);
+        assert_eq!(coverage.stations, 0);
+
+        let empty = StationIndex::new(&[]).coverage(&GeographicalPoint::new(60.0, 10.0), 500.0);
+        assert_eq!(empty.distance, f64::INFINITY);
+        assert_eq!(empty.stations, 0);
+    }
+
+    #[test]
+    fn test_coverage_matches_brute_force() {
+        let stations: Vec<GeographicalPoint> = (0..40)
+            .map(|i| {
+                GeographicalPoint::new(45.0 + (i * 7 % 40) as f64, -150.0 + (i * 37 % 200) as f64)
+            })
+            .collect();
+        let index = StationIndex::new(&stations);
+
+        for lat in (40..90).step_by(5) {
+            for lon in (-180..180).step_by(15) {
+                let point = GeographicalPoint::new(lat as f64, lon as f64);
+                let distances: Vec<f64> = stations
+                    .iter()
+                    .map(|s| degrees_to_km(point.angular_distance(s)))
+                    .collect();
+                let coverage = index.coverage(&point, 800.0);
+                let nearest = distances.iter().copied().fold(f64::INFINITY, f64::min);
+                assert_relative_eq!(coverage.distance, nearest, epsilon = 1e-6);
+                assert_eq!(
+                    coverage.stations as usize,
+                    distances.iter().filter(|d| **d <= 800.0).count()
+                );
+            }
+        }
+    }
+}
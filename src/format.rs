@@ -0,0 +1,415 @@
+use crate::{
+    geo::{GridDefinition, Hemisphere},
+    overlays::{decode_score, ScoreVector, SCORE_SCALE},
+};
+
+/// First bytes of every scores container
+pub const MAGIC: [u8; 4] = *b"NRLS";
+/// Version of the container layout, bumped on any incompatible change
+pub const VERSION: u8 = 1;
+/// Flag set when the scores were combined with derivative predictions, their derivative bits are
+/// only meaningful then
+pub const FLAG_DERIVATIVE: u8 = 0b1;
+
+/// Zigzag value marking a run of unchanged scores, followed by the length of the run
+const RUN_MARKER: u32 = 0;
+/// Upper bound on the scores of a section accepted by `decode`, well above the largest prediction
+/// grid, so that a crafted header cannot make the decoder allocate gigabytes
+pub const MAX_SECTION_SCORES: usize = 1_000_000;
+
+/// Encoded scores of a hemisphere laid on their grid
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoresSection {
+    pub hemisphere: Hemisphere,
+    pub grid: GridDefinition,
+    /// Scores as returned by `encode_score`, ordered as the points of the grid
+    pub scores: Vec<u16>,
+}
+
+impl ScoresSection {
+    /// Decoded scores laid on the points of the grid
+    pub fn score_vectors(&self) -> Vec<ScoreVector> {
+        self.grid
+            .points()
+            .iter()
+            .zip(&self.scores)
+            .map(|(p, s)| ScoreVector {
+                lat: p.lat,
+                lon: p.lon,
+                score: decode_score(*s).score,
+            })
+            .collect()
+    }
+}
+
+/// Self-describing binary representation of the encoded scores.
+///
+/// Every number is little-endian, the header is
+///
+/// | bytes | content                                                 |
+/// |-------|---------------------------------------------------------|
+/// | 4     | magic `NRLS`                                            |
+/// | 1     | version                                                 |
+/// | 1     | flags (`FLAG_DERIVATIVE`)                               |
+/// | 2     | scale, an encoded score is `round(score * scale) << 2`  |
+/// | 8     | timestamp in nanoseconds since the epoch                |
+/// | 1     | number of sections                                      |
+///
+/// followed by the sections, each made of
+///
+/// | bytes | content                                                           |
+/// |-------|-------------------------------------------------------------------|
+/// | 1     | hemisphere (0 north, 1 south)                                     |
+/// | 8 * 2 | first and last latitude (`f64`)                                   |
+/// | 4     | latitude steps                                                    |
+/// | 8 * 2 | first and last longitude (`f64`)                                  |
+/// | 4     | longitude steps                                                   |
+/// | 4     | length of the payload in bytes                                    |
+/// | n     | payload, `lat_steps * lon_steps` scores compressed as shown below |
+///
+/// The payload holds the differences between consecutive scores (the first one with `0`),
+/// wrapping on 16 bits, zigzag and LEB128 encoded. A difference of `0` is always followed by the
+/// LEB128 length of the run of unchanged scores it starts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoresContainer {
+    pub timestamp: u64,
+    pub flags: u8,
+    pub scale: u16,
+    pub sections: Vec<ScoresSection>,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(delta: i16) -> u32 {
+    ((delta << 1) ^ (delta >> 15)) as u16 as u32
+}
+
+fn unzigzag(value: u32) -> i16 {
+    let value = value as u16;
+    ((value >> 1) as i16) ^ -((value & 1) as i16)
+}
+
+/// Delta, zigzag and run-length encoding of the scores
+pub fn compress(scores: &[u16]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut previous = 0u16;
+    let mut idx = 0;
+    while idx < scores.len() {
+        let delta = scores[idx].wrapping_sub(previous) as i16;
+        if delta == 0 {
+            let run = scores[idx..].iter().take_while(|s| **s == previous).count();
+            write_varint(&mut out, RUN_MARKER);
+            write_varint(&mut out, run as u32);
+            idx += run;
+        } else {
+            write_varint(&mut out, zigzag(delta));
+            previous = scores[idx];
+            idx += 1;
+        }
+    }
+    out
+}
+
+/// Reads the little-endian numbers and varints of a container
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(n)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| format!("unexpected end of data at byte {}", self.pos))?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_le_bytes(self.array()?))
+    }
+
+    fn varint(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err("varint too long".to_string())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+}
+
+/// Inverse of `compress`, `len` being the number of scores
+pub fn decompress(payload: &[u8], len: usize) -> Result<Vec<u16>, String> {
+    let mut reader = Reader {
+        bytes: payload,
+        pos: 0,
+    };
+    if len > MAX_SECTION_SCORES {
+        return Err(format!("more than {MAX_SECTION_SCORES} scores"));
+    }
+    // every byte of the payload gives at most one score outside of runs
+    let mut scores = Vec::with_capacity(len.min(payload.len()));
+    let mut previous = 0u16;
+    while !reader.is_empty() {
+        let value = reader.varint()?;
+        if value == RUN_MARKER {
+            let run = reader.varint()? as usize;
+            if run > len - scores.len() {
+                return Err("payload holds more scores than the grid".to_string());
+            }
+            scores.extend(std::iter::repeat_n(previous, run));
+        } else {
+            previous = previous.wrapping_add(unzigzag(value) as u16);
+            scores.push(previous);
+        }
+    }
+    if scores.len() != len {
+        return Err(format!(
+            "payload holds {} scores, {} expected",
+            scores.len(),
+            len
+        ));
+    }
+
+    Ok(scores)
+}
+
+impl ScoresContainer {
+    pub fn new(timestamp: u64, has_derivative: bool, sections: Vec<ScoresSection>) -> Self {
+        ScoresContainer {
+            timestamp,
+            flags: if has_derivative { FLAG_DERIVATIVE } else { 0 },
+            scale: SCORE_SCALE,
+            sections,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend(MAGIC);
+        out.push(VERSION);
+        out.push(self.flags);
+        out.extend(self.scale.to_le_bytes());
+        out.extend(self.timestamp.to_le_bytes());
+        out.push(self.sections.len() as u8);
+
+        for section in &self.sections {
+            out.push(match section.hemisphere {
+                Hemisphere::North => 0,
+                Hemisphere::South => 1,
+            });
+            let grid = &section.grid;
+            out.extend(grid.lat_start.to_le_bytes());
+            out.extend(grid.lat_end.to_le_bytes());
+            out.extend(grid.lat_steps.to_le_bytes());
+            out.extend(grid.lon_start.to_le_bytes());
+            out.extend(grid.lon_end.to_le_bytes());
+            out.extend(grid.lon_steps.to_le_bytes());
+
+            let payload = compress(&section.scores);
+            out.extend((payload.len() as u32).to_le_bytes());
+            out.extend(payload);
+        }
+
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.array::<4>()? != MAGIC {
+            return Err("not a scores container".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("unsupported version {version}"));
+        }
+        let flags = reader.u8()?;
+        let scale = reader.u16()?;
+        let timestamp = reader.u64()?;
+
+        let count = reader.u8()?;
+        let mut sections = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let hemisphere = match reader.u8()? {
+                0 => Hemisphere::North,
+                1 => Hemisphere::South,
+                other => return Err(format!("unknown hemisphere {other}")),
+            };
+            let grid = GridDefinition {
+                lat_start: reader.f64()?,
+                lat_end: reader.f64()?,
+                lat_steps: reader.u32()?,
+                lon_start: reader.f64()?,
+                lon_end: reader.f64()?,
+                lon_steps: reader.u32()?,
+            };
+            let len = (grid.lat_steps as usize)
+                .checked_mul(grid.lon_steps as usize)
+                .filter(|len| *len <= MAX_SECTION_SCORES)
+                .ok_or_else(|| format!("grid of more than {MAX_SECTION_SCORES} scores"))?;
+            let payload_len = reader.u32()? as usize;
+            let scores = decompress(reader.take(payload_len)?, len)?;
+            sections.push(ScoresSection {
+                hemisphere,
+                grid,
+                scores,
+            });
+        }
+        if !reader.is_empty() {
+            return Err("trailing bytes after the last section".to_string());
+        }
+
+        Ok(ScoresContainer {
+            timestamp,
+            flags,
+            scale,
+            sections,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlays::encode_score;
+
+    fn grid(lat_steps: u32, lon_steps: u32) -> GridDefinition {
+        GridDefinition {
+            lat_start: 45.0,
+            lat_end: 85.0,
+            lat_steps,
+            lon_start: -180.0,
+            lon_end: 179.0,
+            lon_steps,
+        }
+    }
+
+    #[test]
+    fn test_compress_round_trip() {
+        let scores = vec![0, 0, 0, 12000, 12001, 65535, 0, 5, 5, 5, 5, 1, 1];
+        let compressed = compress(&scores);
+        assert_eq!(decompress(&compressed, scores.len()), Ok(scores.clone()));
+        assert!(decompress(&compressed, scores.len() + 1).is_err());
+
+        assert_eq!(compress(&[]), Vec::<u8>::new());
+        assert_eq!(decompress(&[], 0), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_compress_runs() {
+        // a quiet map compresses to a single run
+        let scores = vec![0u16; 37 * 130];
+        assert_eq!(compress(&scores).len(), 3);
+    }
+
+    #[test]
+    fn test_container_round_trip() {
+        let north: Vec<u16> = (0..12)
+            .map(|i| encode_score(i as f64 / 2.0, (i % 4) as u8))
+            .collect();
+        let container = ScoresContainer::new(
+            1_700_000_000_000_000_000,
+            true,
+            vec![
+                ScoresSection {
+                    hemisphere: Hemisphere::North,
+                    grid: grid(3, 4),
+                    scores: north,
+                },
+                ScoresSection {
+                    hemisphere: Hemisphere::South,
+                    grid: grid(2, 2),
+                    scores: vec![0, 0, 4, 4],
+                },
+            ],
+        );
+
+        let bytes = container.encode();
+        assert_eq!(&bytes[..5], b"NRLS\x01");
+        assert_eq!(ScoresContainer::decode(&bytes), Ok(container));
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_data() {
+        let container = ScoresContainer::new(
+            0,
+            false,
+            vec![ScoresSection {
+                hemisphere: Hemisphere::North,
+                grid: grid(2, 2),
+                scores: vec![1, 2, 3, 4],
+            }],
+        );
+        let bytes = container.encode();
+
+        assert!(ScoresContainer::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ScoresContainer::decode(b"JSON{}").is_err());
+
+        let mut other_version = bytes.clone();
+        other_version[4] = 2;
+        assert!(ScoresContainer::decode(&other_version).is_err());
+
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(ScoresContainer::decode(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_decode_rejects_huge_grids() {
+        for (lat_steps, lon_steps) in [(u32::MAX, u32::MAX), (1 << 16, 1 << 16), (2000, 1000)] {
+            let container = ScoresContainer::new(
+                0,
+                false,
+                vec![ScoresSection {
+                    hemisphere: Hemisphere::North,
+                    grid: grid(lat_steps, lon_steps),
+                    scores: vec![0; 4],
+                }],
+            );
+            assert!(ScoresContainer::decode(&container.encode()).is_err());
+        }
+
+        // a run cannot expand a few bytes beyond the scores of the grid
+        let mut payload = compress(&[5]);
+        payload.extend([0, 0xff, 0xff, 0xff, 0xff, 0x0f]);
+        assert!(decompress(&payload, 4).is_err());
+        assert!(decompress(&compress(&[1; 10]), MAX_SECTION_SCORES + 1).is_err());
+    }
+}
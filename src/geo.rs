@@ -1,4 +1,5 @@
-use candid::{CandidType, Decode, Deserialize, Encode};
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
 use std::ops::Range;
 
 // Earth radius in meters
@@ -29,6 +30,50 @@ impl GeographicalPoint {
     pub fn lon_rad(&self) -> f64 {
         self.lon.to_radians()
     }
+
+    /// Angular distance to the other point in degrees
+    pub fn angular_distance(&self, other: &GeographicalPoint) -> f64 {
+        let cos = self.lat_rad().sin() * other.lat_rad().sin()
+            + self.lat_rad().cos()
+                * other.lat_rad().cos()
+                * (other.lon_rad() - self.lon_rad()).cos();
+        cos.clamp(-1.0, 1.0).acos().to_degrees()
+    }
+
+    /// Dipole geomagnetic longitude in degrees within [-180, 180], increasing eastward, the
+    /// geographic poles lying on the 180° geomagnetic meridian
+    pub fn geomagnetic_lon(&self) -> f64 {
+        let pole = Hemisphere::North.geomagnetic_pole();
+        let (colat, dlon) = (
+            (90.0 - pole.lat).to_radians(),
+            (self.lon - pole.lon).to_radians(),
+        );
+        let (lat, colat_sin, colat_cos) = (self.lat_rad(), colat.sin(), colat.cos());
+
+        let x = lat.cos() * dlon.cos() * colat_cos - lat.sin() * colat_sin;
+        let y = lat.cos() * dlon.sin();
+        y.atan2(x).to_degrees()
+    }
+
+    /// Point reached travelling `distance` degrees along the great circle leaving this point with
+    /// the given bearing in degrees (clockwise from north), longitude in [-180, 180)
+    pub fn destination(&self, distance: f64, bearing: f64) -> GeographicalPoint {
+        let (d, b) = (distance.to_radians(), bearing.to_radians());
+        let (lat, lon) = (self.lat_rad(), self.lon_rad());
+        let sin_lat = (lat.sin() * d.cos() + lat.cos() * d.sin() * b.cos()).clamp(-1.0, 1.0);
+        let dest_lon = lon + (b.sin() * d.sin() * lat.cos()).atan2(d.cos() - lat.sin() * sin_lat);
+
+        GeographicalPoint::new(
+            sin_lat.asin().to_degrees(),
+            (dest_lon.to_degrees() + 180.0).rem_euclid(360.0) - 180.0,
+        )
+    }
+
+    /// Dipole geomagnetic latitude in degrees, positive in both hemispheres as it is measured from
+    /// the geomagnetic pole of the hemisphere of the point
+    pub fn geomagnetic_lat(&self) -> f64 {
+        90.0 - self.angular_distance(&Hemisphere::of(self.lat).geomagnetic_pole())
+    }
 }
 
 /// Return evenly spaced numbers over a specified interval.
@@ -98,6 +143,77 @@ pub fn geographical_grid(
     result
 }
 
+/// Bounds and resolution of a regular grid as built by [`geographical_grid`], points are ordered
+/// by latitude then longitude
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct GridDefinition {
+    /// First latitude in degrees
+    pub lat_start: f64,
+    /// Last latitude in degrees (included)
+    pub lat_end: f64,
+    pub lat_steps: u32,
+    /// First longitude in degrees
+    pub lon_start: f64,
+    /// Last longitude in degrees (included)
+    pub lon_end: f64,
+    pub lon_steps: u32,
+}
+
+impl GridDefinition {
+    /// Number of points in the grid
+    pub fn size(&self) -> usize {
+        self.lat_steps as usize * self.lon_steps as usize
+    }
+
+    /// Generates the points of the grid, see [`geographical_grid`]
+    pub fn points(&self) -> Vec<GeographicalPoint> {
+        geographical_grid(
+            self.lat_start..self.lat_end,
+            self.lat_steps as usize,
+            self.lon_start..self.lon_end,
+            self.lon_steps as usize,
+        )
+    }
+}
+
+/// Hemisphere covered by a model, each one is fitted and predicted independently
+#[derive(
+    CandidType, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Clone, Copy,
+)]
+pub enum Hemisphere {
+    North,
+    South,
+}
+
+impl Hemisphere {
+    pub const ALL: [Hemisphere; 2] = [Hemisphere::North, Hemisphere::South];
+
+    /// Hemisphere of the given latitude in degrees, the equator belongs to the north
+    pub fn of(lat: f64) -> Self {
+        if lat < 0.0 {
+            Hemisphere::South
+        } else {
+            Hemisphere::North
+        }
+    }
+
+    /// Whether the latitude in degrees lies in this hemisphere
+    pub fn contains(&self, lat: f64) -> bool {
+        match self {
+            Hemisphere::North => (0.0..=90.0).contains(&lat),
+            Hemisphere::South => (-90.0..=0.0).contains(&lat),
+        }
+    }
+
+    /// Geomagnetic pole of the hemisphere, the auroral oval is centred around it
+    pub fn geomagnetic_pole(&self) -> GeographicalPoint {
+        match self {
+            Hemisphere::North => GeographicalPoint::new(80.9, -72.6),
+            Hemisphere::South => GeographicalPoint::new(-80.8, 107.2),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -148,4 +264,83 @@ mod tests {
         assert_relative_eq!(result[5].lat, 90.0);
         assert_relative_eq!(result[5].lon, 180.0);
     }
+
+    #[test]
+    fn test_grid_definition_points() {
+        let grid = GridDefinition {
+            lat_start: 45.0,
+            lat_end: 85.0,
+            lat_steps: 37,
+            lon_start: -180.0,
+            lon_end: 179.0,
+            lon_steps: 130,
+        };
+        let points = grid.points();
+        assert_eq!(points.len(), grid.size());
+        assert_eq!(
+            points,
+            geographical_grid(45.0..85.0, 37, -180.0..179.0, 130)
+        );
+    }
+
+    #[test]
+    fn test_geomagnetic_lon() {
+        let pole = Hemisphere::North.geomagnetic_pole();
+        // points on the meridian of the pole, beyond the geographic pole and before it
+        let beyond = GeographicalPoint::new(70.0, pole.lon + 180.0);
+        assert_relative_eq!(beyond.geomagnetic_lon().abs(), 180.0, epsilon = 1e-9);
+        let before = GeographicalPoint::new(50.0, pole.lon);
+        assert_relative_eq!(before.geomagnetic_lon(), 0.0, epsilon = 1e-9);
+
+        // a quarter of a turn to the east of the pole, up to the tilt of the dipole
+        let east = GeographicalPoint::new(0.0, pole.lon + 90.0);
+        assert_relative_eq!(east.geomagnetic_lon(), 90.0, epsilon = 1e-9);
+        // longitudes increase eastward
+        let tromso = GeographicalPoint::new(69.65, 18.96);
+        let east_of_tromso = GeographicalPoint::new(69.65, 25.0);
+        assert!(east_of_tromso.geomagnetic_lon() > tromso.geomagnetic_lon());
+    }
+
+    #[test]
+    fn test_destination() {
+        let origin = GeographicalPoint::new(60.0, 10.0);
+        let north = origin.destination(10.0, 0.0);
+        assert_relative_eq!(north.lat, 70.0, epsilon = 1e-9);
+        assert_relative_eq!(north.lon, 10.0, epsilon = 1e-9);
+
+        let east = origin.destination(5.0, 90.0);
+        assert_relative_eq!(origin.angular_distance(&east), 5.0, epsilon = 1e-9);
+        assert!(east.lon > 10.0);
+
+        // over the pole and across the antimeridian
+        let over = GeographicalPoint::new(85.0, 170.0).destination(10.0, 0.0);
+        assert_relative_eq!(over.lat, 85.0, epsilon = 1e-9);
+        assert_relative_eq!(over.lon, -10.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_hemisphere() {
+        assert_eq!(Hemisphere::of(69.0), Hemisphere::North);
+        assert_eq!(Hemisphere::of(0.0), Hemisphere::North);
+        assert_eq!(Hemisphere::of(-62.0), Hemisphere::South);
+        assert!(Hemisphere::South.contains(-90.0));
+        assert!(!Hemisphere::South.contains(10.0));
+        assert!(Hemisphere::South.geomagnetic_pole().lat < 0.0);
+    }
+
+    #[test]
+    fn test_geomagnetic_lat() {
+        let pole = Hemisphere::North.geomagnetic_pole();
+        assert_relative_eq!(pole.geomagnetic_lat(), 90.0, epsilon = 1e-5);
+        assert_relative_eq!(
+            GeographicalPoint::new(0.0, 0.0).angular_distance(&GeographicalPoint::new(0.0, 90.0)),
+            90.0
+        );
+
+        // Niemegk lies around 52° geomagnetic latitude
+        let niemegk = GeographicalPoint::new(52.07, 12.68).geomagnetic_lat();
+        assert!((50.0..54.0).contains(&niemegk));
+        let south = GeographicalPoint::new(-69.0, 39.6).geomagnetic_lat();
+        assert!((60.0..80.0).contains(&south));
+    }
 }
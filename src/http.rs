@@ -0,0 +1,596 @@
+use candid::{CandidType, Deserialize};
+use serde_bytes::ByteBuf;
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+
+use crate::{
+    contours::{container_contours, validate_thresholds, DEFAULT_THRESHOLDS},
+    viewline::view_lines,
+    ModelInstance, PredictionStorage,
+};
+
+/// Data only changes when a new prediction is made, at most every minute
+const CACHE_CONTROL_DATA: &str = "public, max-age=60";
+const CACHE_CONTROL_NONE: &str = "no-store";
+/// Score from which the aurora is considered visible when no threshold is requested
+const DEFAULT_VIEW_THRESHOLD: f64 = 3.0;
+
+pub type HeaderField = (String, String);
+
+/// Request as forwarded by the HTTP gateway
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+}
+
+impl HttpResponse {
+    fn new(status_code: u16, content_type: &str, cache_control: &str, body: Vec<u8>) -> Self {
+        HttpResponse {
+            status_code,
+            headers: vec![
+                ("Content-Type".to_string(), content_type.to_string()),
+                ("Content-Length".to_string(), body.len().to_string()),
+                ("Cache-Control".to_string(), cache_control.to_string()),
+                ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+            ],
+            body: ByteBuf::from(body),
+        }
+    }
+
+    fn json(status_code: u16, cache_control: &str, value: &Value) -> Self {
+        Self::new(
+            status_code,
+            "application/json",
+            cache_control,
+            value.to_string().into_bytes(),
+        )
+    }
+
+    fn error(status_code: u16, message: &str) -> Self {
+        Self::json(
+            status_code,
+            CACHE_CONTROL_NONE,
+            &json!({ "error": message }),
+        )
+    }
+}
+
+/// Splits the url into its path and its query parameters
+fn parse_url(url: &str) -> (&str, Vec<(&str, &str)>) {
+    match url.split_once('?') {
+        None => (url, vec![]),
+        Some((path, query)) => (
+            path,
+            query
+                .split('&')
+                .filter(|p| !p.is_empty())
+                .map(|p| p.split_once('=').unwrap_or((p, "")))
+                .collect(),
+        ),
+    }
+}
+
+fn query_flag(params: &[(&str, &str)], name: &str) -> bool {
+    params
+        .iter()
+        .any(|(k, v)| *k == name && matches!(*v, "" | "1" | "true"))
+}
+
+fn health(predictions: &PredictionStorage) -> HttpResponse {
+    HttpResponse::json(
+        200,
+        CACHE_CONTROL_NONE,
+        &json!({
+            "status": "ok",
+            "model_version": env!("CARGO_PKG_VERSION"),
+            "has_scores": !predictions.encoded.is_empty(),
+            "timestamp": predictions.timestamp,
+        }),
+    )
+}
+
+fn metadata(predictions: &PredictionStorage) -> HttpResponse {
+    match predictions.metadata() {
+        None => HttpResponse::error(404, "No scores available yet"),
+        Some(metadata) => HttpResponse::json(200, CACHE_CONTROL_DATA, &json!(metadata)),
+    }
+}
+
+fn scores_json(predictions: &PredictionStorage) -> HttpResponse {
+    match predictions.metadata() {
+        None => HttpResponse::error(404, "No scores available yet"),
+        Some(metadata) => HttpResponse::json(
+            200,
+            CACHE_CONTROL_DATA,
+            &json!({ "metadata": metadata, "scores": predictions.encoded }),
+        ),
+    }
+}
+
+/// Scores as little-endian `u16`, the layout of a `Uint16Array`
+fn scores_binary(predictions: &PredictionStorage) -> HttpResponse {
+    if predictions.encoded.is_empty() {
+        return HttpResponse::error(404, "No scores available yet");
+    }
+
+    HttpResponse::new(
+        200,
+        "application/octet-stream",
+        CACHE_CONTROL_DATA,
+        predictions
+            .encoded
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect(),
+    )
+}
+
+/// Scores in the versioned binary container, see `ScoresContainer`
+fn scores_container(predictions: &PredictionStorage) -> HttpResponse {
+    match predictions.container() {
+        None => HttpResponse::error(404, "No scores available yet"),
+        Some(container) => HttpResponse::new(
+            200,
+            "application/octet-stream",
+            CACHE_CONTROL_DATA,
+            container.encode(),
+        ),
+    }
+}
+
+/// Predicted vectors of every hemisphere as a FeatureCollection of points, along with their score
+/// when available
+fn predictions_geojson(predictions: &PredictionStorage, is_derivative: bool) -> HttpResponse {
+    let mut features: Vec<Value> = vec![];
+    let mut found = false;
+    for (hemisphere, h) in &predictions.hemispheres {
+        let (raw, scores) = if is_derivative {
+            (&h.drv_raw, &h.drv)
+        } else {
+            (&h.abs_raw, &h.abs)
+        };
+        let Some(raw) = raw else {
+            continue;
+        };
+        found = true;
+
+        features.extend(raw.iter().enumerate().map(|(idx, pv)| {
+            let score = scores.as_ref().and_then(|s| s.get(idx)).map(|s| s.score);
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [pv.lon, pv.lat] },
+                "properties": {
+                    "hemisphere": hemisphere,
+                    "i": pv.i,
+                    "j": pv.j,
+                    "k": pv.k,
+                    "score": score,
+                },
+            })
+        }));
+    }
+    if !found {
+        return HttpResponse::error(404, "No predictions available yet");
+    }
+
+    HttpResponse::new(
+        200,
+        "application/geo+json",
+        CACHE_CONTROL_DATA,
+        json!({ "type": "FeatureCollection", "features": features })
+            .to_string()
+            .into_bytes(),
+    )
+}
+
+/// Equatorward edge of the aurora and the line from which it stands on the horizon as a
+/// FeatureCollection of MultiLineStrings, one of each per hemisphere
+fn view_lines_geojson(predictions: &PredictionStorage, threshold: Option<&str>) -> HttpResponse {
+    let threshold = match threshold.map(str::parse::<f64>) {
+        None => DEFAULT_VIEW_THRESHOLD,
+        Some(Ok(t)) if (0.0..=10.0).contains(&t) => t,
+        Some(_) => return HttpResponse::error(400, "Invalid threshold"),
+    };
+    let Some(container) = predictions.container() else {
+        return HttpResponse::error(404, "No scores available yet");
+    };
+
+    let features: Vec<Value> = view_lines(&container, threshold)
+        .iter()
+        .flat_map(|line| {
+            [("overhead", &line.overhead), ("horizon", &line.horizon)].map(|(kind, lines)| {
+                let coordinates: Vec<Vec<[f64; 2]>> = lines
+                    .iter()
+                    .map(|l| l.iter().map(|p| [p.lon, p.lat]).collect())
+                    .collect();
+                json!({
+                    "type": "Feature",
+                    "geometry": { "type": "MultiLineString", "coordinates": coordinates },
+                    "properties": {
+                        "hemisphere": line.hemisphere,
+                        "kind": kind,
+                        "threshold": threshold,
+                    },
+                })
+            })
+        })
+        .collect();
+
+    HttpResponse::new(
+        200,
+        "application/geo+json",
+        CACHE_CONTROL_DATA,
+        json!({ "type": "FeatureCollection", "features": features })
+            .to_string()
+            .into_bytes(),
+    )
+}
+
+/// Areas where the score reaches each threshold as a FeatureCollection of MultiPolygons, one per
+/// hemisphere and threshold
+fn contours_geojson(predictions: &PredictionStorage, thresholds: Option<&str>) -> HttpResponse {
+    let thresholds = match thresholds {
+        None => DEFAULT_THRESHOLDS.to_vec(),
+        Some(t) => match t.split(',').map(str::parse::<f64>).collect() {
+            Ok(t) => t,
+            Err(_) => return HttpResponse::error(400, "Invalid thresholds"),
+        },
+    };
+    if validate_thresholds(&thresholds).is_err() {
+        return HttpResponse::error(400, "Invalid thresholds");
+    }
+    let Some(container) = predictions.container() else {
+        return HttpResponse::error(404, "No scores available yet");
+    };
+
+    let features: Vec<Value> = container_contours(&container, &thresholds)
+        .iter()
+        .map(|contour| {
+            let coordinates: Vec<Vec<Vec<[f64; 2]>>> = contour
+                .polygons
+                .iter()
+                .map(|polygon| {
+                    polygon
+                        .iter()
+                        .map(|ring| ring.iter().map(|p| [p.lon, p.lat]).collect())
+                        .collect()
+                })
+                .collect();
+            json!({
+                "type": "Feature",
+                "geometry": { "type": "MultiPolygon", "coordinates": coordinates },
+                "properties": {
+                    "hemisphere": contour.hemisphere,
+                    "threshold": contour.threshold,
+                },
+            })
+        })
+        .collect();
+
+    HttpResponse::new(
+        200,
+        "application/geo+json",
+        CACHE_CONTROL_DATA,
+        json!({ "type": "FeatureCollection", "features": features })
+            .to_string()
+            .into_bytes(),
+    )
+}
+
+/// Paths whose responses for the composite map are certified, so that the default `icp0.io`
+/// gateway can verify them. The other responses (view lines, contours, `model` parameter, any
+/// query string such as the derivative predictions) are computed on demand and not certified:
+/// they are only served by the `raw.icp0.io` domain, whose responses are not verified and could
+/// be tampered with by a malicious replica.
+pub const CERTIFIED_PATHS: [&str; 8] = [
+    "/",
+    "/health",
+    "/metadata",
+    "/scores",
+    "/scores.json",
+    "/scores.bin",
+    "/scores.nrls",
+    "/predictions.geojson",
+];
+
+/// Path whose certified response answers the request, if any
+pub fn certified_path(request: &HttpRequest) -> Option<&str> {
+    (request.method == "GET")
+        .then_some(request.url.as_str())
+        .filter(|url| CERTIFIED_PATHS.contains(url))
+}
+
+/// Bodies of the responses served at `CERTIFIED_PATHS` for the composite map, to be certified
+/// each time it changes
+pub fn certified_bodies(composite: &PredictionStorage) -> Vec<(&'static str, Vec<u8>)> {
+    CERTIFIED_PATHS
+        .iter()
+        .map(|path| {
+            let request = HttpRequest {
+                method: "GET".to_string(),
+                url: path.to_string(),
+                headers: vec![],
+                body: ByteBuf::new(),
+            };
+            (
+                *path,
+                handle(&request, composite, &BTreeMap::new())
+                    .body
+                    .into_vec(),
+            )
+        })
+        .collect()
+}
+
+/// Routes a gateway request to the stored predictions, those of the composite map unless a
+/// `model` query parameter is given. See `CERTIFIED_PATHS` for the responses that are certified.
+///
+/// * `/health` - status of the canister
+/// * `/metadata` - metadata of the latest scores
+/// * `/scores` or `/scores.json` - latest encoded scores and their metadata
+/// * `/scores.bin` - latest encoded scores as raw little-endian `u16`
+/// * `/scores.nrls` - latest encoded scores in the versioned binary container
+/// * `/predictions.geojson[?derivative=true]` - latest predicted vectors
+/// * `/view-lines.geojson[?threshold=3]` - where the aurora is overhead and on the horizon
+/// * `/contours.geojson[?thresholds=1,3,5]` - areas where the score reaches each threshold
+pub fn handle(
+    request: &HttpRequest,
+    composite: &PredictionStorage,
+    models: &BTreeMap<String, ModelInstance>,
+) -> HttpResponse {
+    if request.method != "GET" && request.method != "HEAD" {
+        return HttpResponse::error(405, "Method not allowed");
+    }
+
+    let (path, params) = parse_url(&request.url);
+    let predictions = match params.iter().find(|(k, _)| *k == "model") {
+        None => composite,
+        Some((_, id)) => match models.get(*id) {
+            Some(model) => &model.predictions,
+            None => return HttpResponse::error(404, "Unknown model"),
+        },
+    };
+    let mut response = match path.trim_end_matches('/') {
+        "" | "/health" => health(predictions),
+        "/metadata" => metadata(predictions),
+        "/scores" | "/scores.json" => scores_json(predictions),
+        "/scores.bin" => scores_binary(predictions),
+        "/scores.nrls" => scores_container(predictions),
+        "/predictions.geojson" => {
+            predictions_geojson(predictions, query_flag(&params, "derivative"))
+        }
+        "/view-lines.geojson" => view_lines_geojson(
+            predictions,
+            params.iter().find(|(k, _)| *k == "threshold").map(|p| p.1),
+        ),
+        "/contours.geojson" => contours_geojson(
+            predictions,
+            params.iter().find(|(k, _)| *k == "thresholds").map(|p| p.1),
+        ),
+        _ => HttpResponse::error(404, "Not found"),
+    };
+
+    if request.method == "HEAD" {
+        response.body = ByteBuf::new();
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::ModelConfig,
+        geo::{GridDefinition, Hemisphere},
+        model::PredictionVector,
+        overlays::ScoreVector,
+        HemispherePredictions, ScoresContainer,
+    };
+
+    fn get(url: &str) -> HttpRequest {
+        HttpRequest {
+            method: "GET".to_string(),
+            url: url.to_string(),
+            headers: vec![],
+            body: ByteBuf::new(),
+        }
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn storage() -> PredictionStorage {
+        let mut predictions = HemispherePredictions::new(GridDefinition {
+            lat_start: 60.0,
+            lat_end: 61.0,
+            lat_steps: 1,
+            lon_start: 10.0,
+            lon_end: 11.0,
+            lon_steps: 2,
+        });
+        predictions.abs_raw = Some(vec![PredictionVector {
+            lon: 10.0,
+            lat: 60.0,
+            i: 100.0,
+            j: 0.0,
+            k: 0.0,
+        }]);
+        predictions.abs = Some(vec![ScoreVector {
+            lon: 10.0,
+            lat: 60.0,
+            score: 3.0,
+        }]);
+
+        let mut storage = PredictionStorage::empty();
+        storage.hemispheres.insert(Hemisphere::North, predictions);
+        storage.encoded = vec![12000, 258];
+        storage.timestamp = 42;
+        storage
+    }
+
+    fn models() -> BTreeMap<String, ModelInstance> {
+        let mut model = ModelInstance::new(ModelConfig::default());
+        model.predictions = storage();
+        model.predictions.encoded = vec![4];
+        BTreeMap::from([("fennoscandia".to_string(), model)])
+    }
+
+    #[test]
+    fn test_certified_bodies_match_responses() {
+        for (path, body) in certified_bodies(&storage()) {
+            let response = handle(&get(path), &storage(), &models());
+            assert_eq!(response.body.as_slice(), body.as_slice(), "{path}");
+            assert_eq!(certified_path(&get(path)), Some(path));
+        }
+
+        assert_eq!(certified_path(&get("/scores.bin?model=fennoscandia")), None);
+        assert_eq!(certified_path(&get("/contours.geojson")), None);
+        assert_eq!(
+            certified_path(&get("/predictions.geojson?model=fennoscandia")),
+            None
+        );
+        assert_eq!(
+            certified_path(&get("/predictions.geojson")),
+            Some("/predictions.geojson")
+        );
+        let mut head = get("/scores");
+        head.method = "HEAD".to_string();
+        assert_eq!(certified_path(&head), None);
+    }
+
+    #[test]
+    fn test_parse_url() {
+        let (path, params) = parse_url("/predictions.geojson?derivative=true&x");
+        assert_eq!(path, "/predictions.geojson");
+        assert_eq!(params, vec![("derivative", "true"), ("x", "")]);
+        assert!(query_flag(&params, "derivative"));
+        assert!(!query_flag(&params, "other"));
+    }
+
+    #[test]
+    fn test_scores_binary() {
+        let response = handle(&get("/scores.bin"), &storage(), &models());
+        assert_eq!(response.status_code, 200);
+        assert_eq!(
+            header(&response, "Content-Type"),
+            Some("application/octet-stream")
+        );
+        assert_eq!(response.body.as_slice(), &[0xe0, 0x2e, 0x02, 0x01]);
+    }
+
+    #[test]
+    fn test_predictions_geojson() {
+        let response = handle(&get("/predictions.geojson"), &storage(), &models());
+        assert_eq!(response.status_code, 200);
+        assert_eq!(header(&response, "Cache-Control"), Some(CACHE_CONTROL_DATA));
+
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["type"], "FeatureCollection");
+        assert_eq!(
+            body["features"][0]["geometry"]["coordinates"],
+            json!([10.0, 60.0])
+        );
+        assert_eq!(body["features"][0]["properties"]["score"], json!(3.0));
+        assert_eq!(body["features"][0]["properties"]["hemisphere"], "North");
+
+        let response = handle(
+            &get("/predictions.geojson?derivative=1"),
+            &storage(),
+            &models(),
+        );
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            handle(&get("/unknown"), &storage(), &models()).status_code,
+            404
+        );
+        assert_eq!(
+            handle(&get("/scores"), &PredictionStorage::empty(), &models()).status_code,
+            404
+        );
+
+        let mut post = get("/scores");
+        post.method = "POST".to_string();
+        assert_eq!(handle(&post, &storage(), &models()).status_code, 405);
+    }
+
+    #[test]
+    fn test_model_param() {
+        let response = handle(
+            &get("/scores.bin?model=fennoscandia"),
+            &storage(),
+            &models(),
+        );
+        assert_eq!(response.body.as_slice(), &[0x04, 0x00]);
+
+        let response = handle(&get("/scores.bin?model=other"), &storage(), &models());
+        assert_eq!(response.status_code, 404);
+    }
+
+    #[test]
+    fn test_scores_container() {
+        let response = handle(&get("/scores.nrls"), &storage(), &models());
+        assert_eq!(response.status_code, 200);
+
+        let container = ScoresContainer::decode(&response.body).unwrap();
+        assert_eq!(container.timestamp, 42);
+        assert_eq!(container.sections.len(), 1);
+        assert_eq!(container.sections[0].hemisphere, Hemisphere::North);
+        assert_eq!(container.sections[0].scores, vec![12000, 258]);
+    }
+
+    #[test]
+    fn test_view_lines_geojson() {
+        let response = handle(&get("/view-lines.geojson"), &storage(), &models());
+        assert_eq!(response.status_code, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        let features = body["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[1]["properties"]["kind"], "horizon");
+        assert_eq!(features[1]["geometry"]["type"], "MultiLineString");
+
+        for threshold in ["abc", "11"] {
+            let url = format!("/view-lines.geojson?threshold={threshold}");
+            assert_eq!(handle(&get(&url), &storage(), &models()).status_code, 400);
+        }
+    }
+
+    #[test]
+    fn test_contours_geojson() {
+        let response = handle(
+            &get("/contours.geojson?thresholds=1,2.5"),
+            &storage(),
+            &models(),
+        );
+        assert_eq!(response.status_code, 200);
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        let features = body["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2);
+        assert_eq!(features[1]["properties"]["threshold"], 2.5);
+        assert_eq!(features[0]["geometry"]["type"], "MultiPolygon");
+
+        for thresholds in ["", "1,x", "0"] {
+            let url = format!("/contours.geojson?thresholds={thresholds}");
+            assert_eq!(handle(&get(&url), &storage(), &models()).status_code, 400);
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use candid::{CandidType, Deserialize};
+use ndarray::Array2;
+use serde::Serialize;
+
+use std::collections::BTreeMap;
+
+use crate::{geo::Hemisphere, model::TPredStatus};
+
+/// Intervals are given in seconds, a day at most
+const MAX_INTERVAL_SECS: u64 = 86_400;
+/// Predictions of a pending fit that may trap before it is dropped as failed
+pub const MAX_PREDICT_ATTEMPTS: u32 = 3;
+
+/// Scheduling of the recomputations run by the canister timers
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct JobsConfig {
+    /// Whether the timers are running at all
+    pub enabled: bool,
+    /// Interval at which pending fits are predicted and scored
+    pub predict_interval_secs: u64,
+    /// Interval at which a missing prediction transfer matrix is computed, a chunk at a time
+    pub fit_pred_interval_secs: u64,
+}
+
+impl Default for JobsConfig {
+    fn default() -> Self {
+        JobsConfig {
+            enabled: true,
+            predict_interval_secs: 30,
+            fit_pred_interval_secs: 10,
+        }
+    }
+}
+
+impl JobsConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let intervals = [
+            ("predict_interval_secs", self.predict_interval_secs),
+            ("fit_pred_interval_secs", self.fit_pred_interval_secs),
+        ];
+        for (name, interval) in intervals {
+            if !(1..=MAX_INTERVAL_SECS).contains(&interval) {
+                return Err(format!("{name}: must be within 1..{MAX_INTERVAL_SECS}"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Amplitudes of a fit waiting for its prediction
+#[derive(Debug, Clone)]
+pub struct PendingFit {
+    pub model: String,
+    pub hemisphere: Hemisphere,
+    pub is_derivative: bool,
+    pub sec_amps: Array2<f64>,
+    /// Time of the fit in nanoseconds since the epoch
+    pub fitted_at: u64,
+    /// Predictions of the fit started so far, those that trapped leaving it pending
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct JobsState {
+    pub config: JobsConfig,
+    /// Latest fit of each kind, model and hemisphere not predicted yet, keyed by `is_derivative`
+    /// first so that absolute fits come first
+    pending: BTreeMap<(bool, String, Hemisphere), PendingFit>,
+    /// Time of the last prediction made by a job in nanoseconds since the epoch
+    pub last_prediction: Option<u64>,
+    /// Number of predictions made by jobs since installation
+    pub predictions: u64,
+    /// Number of fits dropped after trapping `MAX_PREDICT_ATTEMPTS` times
+    failures: u64,
+    /// Last fit dropped after trapping `MAX_PREDICT_ATTEMPTS` times
+    last_failure: Option<PendingStatus>,
+}
+
+impl JobsState {
+    /// Queue a fit for prediction, replacing any older fit of the same kind, model and hemisphere
+    pub fn queue(&mut self, fit: PendingFit) {
+        self.pending
+            .insert((fit.is_derivative, fit.model.clone(), fit.hemisphere), fit);
+    }
+
+    /// Next fit to predict among the given hemispheres of models, absolute fits first. The fit
+    /// stays pending until `complete` is called with it, counting an attempt so that a fit whose
+    /// prediction keeps trapping is dropped as failed after `MAX_PREDICT_ATTEMPTS` of them.
+    pub fn next_pending(&mut self, ready: &[(String, Hemisphere)]) -> Option<PendingFit> {
+        loop {
+            let key = self
+                .pending
+                .keys()
+                .find(|(_, model, hemisphere)| {
+                    ready.iter().any(|(m, h)| m == model && h == hemisphere)
+                })?
+                .clone();
+            let fit = self.pending.get_mut(&key).unwrap();
+            if fit.attempts < MAX_PREDICT_ATTEMPTS {
+                fit.attempts += 1;
+                return Some(fit.clone());
+            }
+
+            let failed = self.pending.remove(&key).unwrap();
+            self.failures += 1;
+            self.last_failure = Some(PendingStatus::from(&failed));
+        }
+    }
+
+    /// Remove a fit once predicted, unless a newer fit replaced it in the meantime
+    pub fn complete(&mut self, fit: &PendingFit) {
+        let key = (fit.is_derivative, fit.model.clone(), fit.hemisphere);
+        if self
+            .pending
+            .get(&key)
+            .is_some_and(|p| p.fitted_at == fit.fitted_at)
+        {
+            self.pending.remove(&key);
+        }
+    }
+
+    /// Drop the pending fits of the hemisphere of the model
+    pub fn clear_pending(&mut self, model: &str, hemisphere: Hemisphere) {
+        self.pending
+            .retain(|(_, m, h), _| m != model || *h != hemisphere);
+    }
+
+    /// Status of the jobs given the progress of the prediction transfer matrix of every modelled
+    /// hemisphere
+    pub fn status(&self, hemispheres: Vec<HemisphereTPredStatus>) -> JobsStatus {
+        JobsStatus {
+            config: self.config.clone(),
+            pending: self.pending.values().map(PendingStatus::from).collect(),
+            t_pred: hemispheres
+                .iter()
+                .map(|h| h.t_pred)
+                .fold(TPredStatus::Missing, TPredStatus::merge),
+            hemispheres,
+            last_prediction: self.last_prediction,
+            predictions: self.predictions,
+            failures: self.failures,
+            last_failure: self.last_failure.clone(),
+        }
+    }
+}
+
+/// Fit waiting for its prediction
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct PendingStatus {
+    pub model: String,
+    pub hemisphere: Hemisphere,
+    pub is_derivative: bool,
+    pub fitted_at: u64,
+    /// Predictions started so far, see `MAX_PREDICT_ATTEMPTS`
+    pub attempts: u32,
+}
+
+impl From<&PendingFit> for PendingStatus {
+    fn from(fit: &PendingFit) -> Self {
+        PendingStatus {
+            model: fit.model.clone(),
+            hemisphere: fit.hemisphere,
+            is_derivative: fit.is_derivative,
+            fitted_at: fit.fitted_at,
+            attempts: fit.attempts,
+        }
+    }
+}
+
+/// Progress of the prediction transfer matrix of a modelled hemisphere
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct HemisphereTPredStatus {
+    pub model: String,
+    pub hemisphere: Hemisphere,
+    /// `Missing` until the hemisphere is fitted
+    pub t_pred: TPredStatus,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct JobsStatus {
+    pub config: JobsConfig,
+    /// Fits waiting for their prediction, in the order they will be predicted
+    pub pending: Vec<PendingStatus>,
+    /// Progress of the prediction transfer matrices of every model, the hemispheres not fitted
+    /// yet being left out
+    pub t_pred: TPredStatus,
+    /// Progress of the prediction transfer matrix of every modelled hemisphere of every model
+    pub hemispheres: Vec<HemisphereTPredStatus>,
+    pub last_prediction: Option<u64>,
+    pub predictions: u64,
+    /// Number of fits dropped after their prediction trapped `MAX_PREDICT_ATTEMPTS` times
+    pub failures: u64,
+    pub last_failure: Option<PendingStatus>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit(model: &str, hemisphere: Hemisphere, is_derivative: bool, fitted_at: u64) -> PendingFit {
+        PendingFit {
+            model: model.to_string(),
+            hemisphere,
+            is_derivative,
+            sec_amps: Array2::zeros((1, 1)),
+            fitted_at,
+            attempts: 0,
+        }
+    }
+
+    fn ready(models: &[&str]) -> Vec<(String, Hemisphere)> {
+        models
+            .iter()
+            .flat_map(|m| Hemisphere::ALL.map(|h| (m.to_string(), h)))
+            .collect()
+    }
+
+    /// Next pending fit, predicted successfully
+    fn take(state: &mut JobsState, ready: &[(String, Hemisphere)]) -> Option<PendingFit> {
+        let fit = state.next_pending(ready)?;
+        state.complete(&fit);
+        Some(fit)
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(JobsConfig::default().validate(), Ok(()));
+
+        let config = JobsConfig {
+            predict_interval_secs: 0,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_pending_order() {
+        use Hemisphere::{North, South};
+
+        let all = ready(&["default", "nordic"]);
+        let mut state = JobsState::default();
+        assert!(take(&mut state, &all).is_none());
+
+        state.queue(fit("default", North, true, 1));
+        state.queue(fit("default", South, true, 2));
+        state.queue(fit("default", North, false, 3));
+        state.queue(fit("default", North, false, 4));
+        state.queue(fit("nordic", North, false, 5));
+
+        let status = state.status(vec![]);
+        let fitted_at: Vec<u64> = status.pending.iter().map(|p| p.fitted_at).collect();
+        assert_eq!(fitted_at, vec![4, 5, 1, 2]);
+
+        // hemispheres not ready are skipped
+        let next = take(&mut state, &[("default".to_string(), South)]).unwrap();
+        assert_eq!((next.hemisphere, next.is_derivative), (South, true));
+
+        let next = take(&mut state, &ready(&["nordic"])).unwrap();
+        assert_eq!(next.fitted_at, 5);
+
+        let next = take(&mut state, &all).unwrap();
+        assert!(!next.is_derivative);
+        assert_eq!(next.fitted_at, 4);
+        assert_eq!(take(&mut state, &all).unwrap().fitted_at, 1);
+        assert!(take(&mut state, &all).is_none());
+
+        state.queue(fit("default", North, true, 6));
+        state.queue(fit("default", South, true, 7));
+        state.queue(fit("nordic", North, true, 8));
+        state.clear_pending("default", North);
+        assert_eq!(take(&mut state, &all).unwrap().fitted_at, 7);
+        assert_eq!(take(&mut state, &all).unwrap().fitted_at, 8);
+        assert!(take(&mut state, &all).is_none());
+    }
+
+    #[test]
+    fn test_status_leaves_missing_hemispheres_out() {
+        use Hemisphere::{North, South};
+
+        let hemisphere = |hemisphere, t_pred| HemisphereTPredStatus {
+            model: "default".to_string(),
+            hemisphere,
+            t_pred,
+        };
+        let hemispheres = vec![
+            hemisphere(North, TPredStatus::Ready { total: 10 }),
+            hemisphere(South, TPredStatus::Missing),
+        ];
+
+        let status = JobsState::default().status(hemispheres.clone());
+        assert_eq!(status.t_pred, TPredStatus::Ready { total: 10 });
+        assert_eq!(status.hemispheres, hemispheres);
+        assert_eq!(
+            JobsState::default().status(vec![]).t_pred,
+            TPredStatus::Missing
+        );
+    }
+
+    #[test]
+    fn test_trapping_fit_is_dropped() {
+        use Hemisphere::North;
+
+        let all = ready(&["default"]);
+        let mut state = JobsState::default();
+        state.queue(fit("default", North, false, 1));
+        state.queue(fit("default", North, true, 2));
+
+        // predictions trapping before `complete` leave the fit pending
+        for attempt in 1..=MAX_PREDICT_ATTEMPTS {
+            let next = state.next_pending(&all).unwrap();
+            assert_eq!((next.fitted_at, next.attempts), (1, attempt));
+        }
+        assert_eq!(state.status(vec![]).pending[0].attempts, 3);
+
+        // then it is dropped and the next fit comes
+        let next = state.next_pending(&all).unwrap();
+        assert_eq!(next.fitted_at, 2);
+        let status = state.status(vec![]);
+        assert_eq!(status.failures, 1);
+        assert_eq!(status.last_failure.unwrap().fitted_at, 1);
+
+        // a newer fit queued during the prediction stays pending
+        state.queue(fit("default", North, true, 3));
+        state.complete(&next);
+        assert_eq!(state.next_pending(&all).unwrap().fitted_at, 3);
+    }
+}
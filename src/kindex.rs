@@ -0,0 +1,418 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use crate::geo::GeographicalPoint;
+
+/// K indices are computed over 3-hour UT windows (00-03, 03-06, ...)
+pub const WINDOW_NANOS: u64 = 3 * 3_600 * 1_000_000_000;
+/// Upper bound on the number of virtual stations
+const MAX_STATIONS: usize = 200;
+
+/// Lower bounds of the range of each K for a K9 limit of 500 nT (Niemegk), scaled linearly for
+/// other stations
+const K_THRESHOLDS: [f64; 10] = [0.0, 5.0, 10.0, 20.0, 40.0, 70.0, 120.0, 200.0, 330.0, 500.0];
+const REFERENCE_K9: f64 = 500.0;
+
+/// K9 limits in nT of observatories at a given geomagnetic latitude, interpolated linearly and
+/// kept constant past both ends, for stations without an explicit limit
+const K9_LIMITS: [(f64, f64); 6] = [
+    (20.0, 300.0),
+    (48.0, 500.0),
+    (54.0, 750.0),
+    (58.0, 1000.0),
+    (63.0, 2500.0),
+    (90.0, 2500.0),
+];
+
+/// Location at which the predicted field is followed as if an observatory stood there
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct VirtualStation {
+    /// Unique name, usually the IAGA code of the observatory it stands for
+    pub name: String,
+    pub lat: f64,
+    pub lon: f64,
+    /// Whether the station takes part in the Kp estimate
+    pub kp_network: bool,
+    /// K9 limit in nT assigned to the observatory, derived from the geomagnetic latitude of the
+    /// station when `None`
+    pub k9: Option<f64>,
+}
+
+impl VirtualStation {
+    fn new(name: &str, lat: f64, lon: f64, k9: f64) -> Self {
+        VirtualStation {
+            name: name.to_string(),
+            lat,
+            lon,
+            kp_network: true,
+            k9: Some(k9),
+        }
+    }
+
+    pub fn location(&self) -> GeographicalPoint {
+        GeographicalPoint::new(self.lat, self.lon)
+    }
+
+    /// K9 limit of the station in nT, from its geomagnetic latitude unless set explicitly
+    pub fn k9(&self) -> f64 {
+        self.k9
+            .unwrap_or_else(|| k9_limit(self.location().geomagnetic_lat()))
+    }
+
+    /// Observatories of the Kp network covered by the default prediction grid, with their
+    /// official K9 limits
+    pub fn kp_network() -> Vec<VirtualStation> {
+        vec![
+            VirtualStation::new("MEA", 54.62, -113.35, 1000.0),
+            VirtualStation::new("SIT", 57.06, -135.33, 1000.0),
+            VirtualStation::new("OTT", 45.40, -75.55, 500.0),
+            VirtualStation::new("LER", 60.14, -1.18, 1000.0),
+            VirtualStation::new("ESK", 55.31, -3.21, 750.0),
+            VirtualStation::new("HAD", 50.99, -4.48, 500.0),
+            VirtualStation::new("WNG", 53.74, 9.07, 500.0),
+            VirtualStation::new("NGK", 52.07, 12.68, 500.0),
+            VirtualStation::new("BFE", 55.63, 11.67, 500.0),
+            VirtualStation::new("UPS", 59.90, 17.35, 500.0),
+        ]
+    }
+}
+
+pub fn validate_stations(stations: &[VirtualStation]) -> Result<(), String> {
+    if stations.len() > MAX_STATIONS {
+        return Err(format!("more than {MAX_STATIONS} stations"));
+    }
+    for (idx, station) in stations.iter().enumerate() {
+        if station.name.is_empty() {
+            return Err(format!("station {idx}: name must not be empty"));
+        }
+        if stations[..idx].iter().any(|s| s.name == station.name) {
+            return Err(format!("{}: duplicated name", station.name));
+        }
+        if !(-90.0..=90.0).contains(&station.lat) || !(-180.0..=360.0).contains(&station.lon) {
+            return Err(format!("{}: invalid coordinates", station.name));
+        }
+        if station.k9.is_some_and(|k9| !(k9.is_finite() && k9 > 0.0)) {
+            return Err(format!("{}: K9 limit must be positive", station.name));
+        }
+    }
+
+    Ok(())
+}
+
+/// K9 limit in nT at the given geomagnetic latitude in degrees
+pub fn k9_limit(geomagnetic_lat: f64) -> f64 {
+    let lat = geomagnetic_lat.abs();
+    let (first, last) = (K9_LIMITS[0], K9_LIMITS[K9_LIMITS.len() - 1]);
+    if lat <= first.0 {
+        return first.1;
+    }
+    if lat >= last.0 {
+        return last.1;
+    }
+
+    let idx = K9_LIMITS.iter().position(|(l, _)| *l > lat).unwrap();
+    let ((lat0, k0), (lat1, k1)) = (K9_LIMITS[idx - 1], K9_LIMITS[idx]);
+    k0 + (k1 - k0) * (lat - lat0) / (lat1 - lat0)
+}
+
+/// K index of a range of the horizontal components in nT at a station of the given K9 limit
+pub fn k_index(range: f64, k9: f64) -> u8 {
+    let scale = k9 / REFERENCE_K9;
+    K_THRESHOLDS
+        .iter()
+        .rposition(|t| range >= t * scale)
+        .unwrap_or(0) as u8
+}
+
+/// Start of the 3-hour window holding the timestamp in nanoseconds since the epoch
+pub fn window_start(timestamp: u64) -> u64 {
+    timestamp - timestamp % WINDOW_NANOS
+}
+
+/// Extent of the predicted horizontal components at a station over a window
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct StationWindow {
+    start: u64,
+    i: (f64, f64),
+    j: (f64, f64),
+}
+
+impl StationWindow {
+    fn new(start: u64, i: f64, j: f64) -> Self {
+        StationWindow {
+            start,
+            i: (i, i),
+            j: (j, j),
+        }
+    }
+
+    fn add(&mut self, i: f64, j: f64) {
+        self.i = (self.i.0.min(i), self.i.1.max(i));
+        self.j = (self.j.0.min(j), self.j.1.max(j));
+    }
+
+    /// Largest range of the two horizontal components
+    fn range(&self) -> f64 {
+        (self.i.1 - self.i.0).max(self.j.1 - self.j.0)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct StationState {
+    /// Window being filled
+    window: Option<StationWindow>,
+    /// K index of the last completed window along with its start
+    last: Option<(u64, u8)>,
+}
+
+/// Local K index of a virtual station
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct StationK {
+    pub station: VirtualStation,
+    /// K9 limit of the station in nT
+    pub k9: f64,
+    /// Start of the current window in nanoseconds since the epoch
+    pub window_start: Option<u64>,
+    /// Largest range of the horizontal components over the current window so far, in nT
+    pub range: Option<f64>,
+    /// K index of the current window so far
+    pub k: Option<u8>,
+    /// K index of the last completed window
+    pub last_k: Option<u8>,
+}
+
+/// Kp estimated from the local K indices of the Kp network stations
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct KpEstimate {
+    /// Kp in thirds (`2.333` standing for `2+`)
+    pub kp: f64,
+    /// Start of the window the estimate is made over, in nanoseconds since the epoch
+    pub window_start: u64,
+    /// Number of stations the estimate is made from
+    pub stations: u32,
+}
+
+/// Windows of the predicted horizontal components at the virtual stations
+#[derive(Debug, Clone)]
+pub struct KIndexState {
+    stations: Vec<VirtualStation>,
+    states: BTreeMap<String, StationState>,
+}
+
+impl Default for KIndexState {
+    fn default() -> Self {
+        KIndexState::new(VirtualStation::kp_network())
+    }
+}
+
+impl KIndexState {
+    pub fn new(stations: Vec<VirtualStation>) -> Self {
+        KIndexState {
+            stations,
+            states: BTreeMap::new(),
+        }
+    }
+
+    pub fn stations(&self) -> &[VirtualStation] {
+        &self.stations
+    }
+
+    /// Record a prediction made at `timestamp`, `sample` giving the predicted `i` and `j`
+    /// components at a station or `None` when it is not covered
+    pub fn record(
+        &mut self,
+        timestamp: u64,
+        sample: impl Fn(&GeographicalPoint) -> Option<(f64, f64)>,
+    ) {
+        let start = window_start(timestamp);
+        for station in &self.stations {
+            let Some((i, j)) = sample(&station.location()) else {
+                continue;
+            };
+
+            let state = self.states.entry(station.name.clone()).or_default();
+            match state.window.as_mut() {
+                Some(window) if window.start == start => window.add(i, j),
+                // samples older than the current window are dropped
+                Some(window) if window.start > start => {}
+                previous => {
+                    if let Some(window) = previous {
+                        state.last = Some((window.start, k_index(window.range(), station.k9())));
+                    }
+                    state.window = Some(StationWindow::new(start, i, j));
+                }
+            }
+        }
+    }
+
+    pub fn local_k(&self) -> Vec<StationK> {
+        self.stations
+            .iter()
+            .map(|station| {
+                let state = self.states.get(&station.name);
+                let window = state.and_then(|s| s.window);
+                let k9 = station.k9();
+                StationK {
+                    station: station.clone(),
+                    k9,
+                    window_start: window.map(|w| w.start),
+                    range: window.map(|w| w.range()),
+                    k: window.map(|w| k_index(w.range(), k9)),
+                    last_k: state.and_then(|s| s.last).map(|(_, k)| k),
+                }
+            })
+            .collect()
+    }
+
+    /// Mean of the K indices of the Kp network stations over the most recent window, rounded to
+    /// thirds. `None` until a station of the network was sampled.
+    pub fn kp(&self) -> Option<KpEstimate> {
+        let network: Vec<StationK> = self
+            .local_k()
+            .into_iter()
+            .filter(|s| s.station.kp_network)
+            .collect();
+        let window_start = network.iter().filter_map(|s| s.window_start).max()?;
+        let ks: Vec<f64> = network
+            .iter()
+            .filter(|s| s.window_start == Some(window_start))
+            .filter_map(|s| s.k.map(f64::from))
+            .collect();
+
+        let mean = ks.iter().sum::<f64>() / ks.len() as f64;
+        Some(KpEstimate {
+            kp: (mean * 3.0).round() / 3.0,
+            window_start,
+            stations: ks.len() as u32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    const HOUR: u64 = 3_600 * 1_000_000_000;
+
+    #[test]
+    fn test_k9_limit() {
+        assert_relative_eq!(k9_limit(48.0), 500.0);
+        assert_relative_eq!(k9_limit(56.0), 875.0);
+        assert_relative_eq!(k9_limit(-70.0), 2500.0);
+        assert_relative_eq!(k9_limit(5.0), 300.0);
+    }
+
+    #[test]
+    fn test_k_index() {
+        assert_eq!(k_index(0.0, 500.0), 0);
+        assert_eq!(k_index(4.9, 500.0), 0);
+        assert_eq!(k_index(45.0, 500.0), 4);
+        assert_eq!(k_index(600.0, 500.0), 9);
+        // the same range is quieter further north
+        assert_eq!(k_index(45.0, 2500.0), 1);
+        assert_eq!(k_index(1000.0, 2500.0), 7);
+    }
+
+    #[test]
+    fn test_windows() {
+        let station = VirtualStation {
+            name: "NGK".to_string(),
+            lat: 52.07,
+            lon: 12.68,
+            kp_network: true,
+            k9: None,
+        };
+        let k9 = station.k9();
+        let mut state = KIndexState::new(vec![station]);
+        assert_eq!(state.kp(), None);
+
+        state.record(HOUR, |_| Some((0.0, 0.0)));
+        state.record(2 * HOUR, |_| Some((k9 * 0.1, -5.0)));
+        let local = &state.local_k()[0];
+        assert_eq!(local.window_start, Some(0));
+        assert_relative_eq!(local.range.unwrap(), k9 * 0.1);
+        assert_eq!(local.k, Some(4));
+        assert_eq!(local.last_k, None);
+
+        // stations not covered by the prediction are left untouched
+        state.record(2 * HOUR, |_| None);
+
+        state.record(4 * HOUR, |_| Some((10.0, 10.0)));
+        // late samples of the previous window are dropped
+        state.record(2 * HOUR, |_| Some((1_000.0, 0.0)));
+        let local = &state.local_k()[0];
+        assert_eq!(local.window_start, Some(3 * HOUR));
+        assert_eq!(local.k, Some(0));
+        assert_eq!(local.last_k, Some(4));
+
+        let kp = state.kp().unwrap();
+        assert_eq!(kp.window_start, 3 * HOUR);
+        assert_eq!(kp.stations, 1);
+        assert_relative_eq!(kp.kp, 0.0);
+    }
+
+    #[test]
+    fn test_kp_thirds() {
+        let mut stations = VirtualStation::kp_network();
+        stations.truncate(3);
+        stations[2].kp_network = false;
+        let mea = stations[0].location();
+        let k9s: Vec<_> = stations.iter().map(|s| (s.location(), s.k9())).collect();
+        let mut state = KIndexState::new(stations);
+
+        // K 4 at MEA, 5 at SIT and 9 at OTT
+        let range = |p: &GeographicalPoint, k: usize| {
+            let k9 = k9s.iter().find(|(l, _)| l == p).unwrap().1;
+            K_THRESHOLDS[k] * k9 / REFERENCE_K9
+        };
+        state.record(0, |_| Some((0.0, 0.0)));
+        state.record(1, |p| {
+            let k = if *p == mea {
+                4
+            } else if p.lat < 50.0 {
+                9
+            } else {
+                5
+            };
+            Some((range(p, k), 0.0))
+        });
+
+        // OTT is left out of the estimate, (4 + 5) / 2 rounds to 5-
+        let kp = state.kp().unwrap();
+        assert_eq!(kp.stations, 2);
+        assert_relative_eq!(kp.kp, 4.0 + 2.0 / 3.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_validate_stations() {
+        assert_eq!(validate_stations(&VirtualStation::kp_network()), Ok(()));
+
+        let mut stations = VirtualStation::kp_network();
+        stations[1].name = stations[0].name.clone();
+        assert!(validate_stations(&stations).is_err());
+
+        let mut stations = VirtualStation::kp_network();
+        stations[0].lat = 100.0;
+        assert!(validate_stations(&stations).is_err());
+
+        let mut stations = VirtualStation::kp_network();
+        stations[0].k9 = Some(0.0);
+        assert!(validate_stations(&stations).is_err());
+    }
+
+    #[test]
+    fn test_official_k9() {
+        let stations = VirtualStation::kp_network();
+        let k9 = |name: &str| stations.iter().find(|s| s.name == name).unwrap().k9();
+        assert_relative_eq!(k9("NGK"), 500.0);
+        assert_relative_eq!(k9("UPS"), 500.0);
+        assert_relative_eq!(k9("MEA"), 1000.0);
+
+        // the latitude table is only a fallback, it would give Uppsala a much higher limit
+        let mut ups = stations.iter().find(|s| s.name == "UPS").unwrap().clone();
+        ups.k9 = None;
+        assert!(ups.k9() > 700.0);
+    }
+}
@@ -1,202 +1,1543 @@
-use geo::geographical_grid;
+use alerts::{AlertEvent, AlertsState, Subscription};
+use certification::{certified_tree, witness, CertifiedScores, CertifiedTree};
+use clouds::{CloudCover, Visibility};
+use config::{validate_model_id, ModelConfig, ModelDefinition};
+use contours::{validate_thresholds, Contour, DEFAULT_THRESHOLDS};
+use coverage::{Coverage, StationIndex};
+use geo::{GeographicalPoint, GridDefinition, Hemisphere};
+use http::{HttpRequest, HttpResponse};
 use ic_cdk::caller;
-use model::{ObservationVector, PredictionVector, SECS};
-use overlays::{IntoScores, Overlays, ScoreVector};
+use ic_cdk_timers::TimerId;
+use jobs::{HemisphereTPredStatus, JobsConfig, JobsState, JobsStatus, PendingFit};
+use kindex::{validate_stations, KIndexState, KpEstimate, StationK, VirtualStation};
+use model::{
+    validate_observations, ObservationVector, PredictionVector, TPredStatus, POINT_CACHE_SIZE, SECS,
+};
+use overlays::{IntoScores, OvalActivity, Overlays, ScoreVector};
+use scoring::ScoringProfile;
+use viewline::ViewLine;
 
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
 
-use candid::Principal;
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+use serde_bytes::ByteBuf;
 
+mod alerts;
+mod certification;
+mod clouds;
+mod composite;
+mod config;
+mod contours;
+mod coverage;
+mod format;
 mod geo;
+mod http;
+mod jobs;
+mod kindex;
 mod model;
 mod overlays;
+mod scoring;
+mod solar;
 mod sphere;
 mod svd;
 mod t_df;
+mod transfer;
+mod viewline;
 
+// Score encoding, exposed for clients decoding the scores from Rust or wasm
+pub use format::{ScoresContainer, ScoresSection};
+pub use overlays::{
+    decode_score, encode_score, DecodedScore, DERIVATIVE_DRIVEN, DERIVATIVE_RISING,
+};
+
+/// Latest predictions of a hemisphere
 #[derive(Clone)]
-struct PredictionStorage {
+struct HemispherePredictions {
     /// Predictions from the absolute observations
     abs: Option<Vec<ScoreVector>>,
     /// Predictions from the derivative
     drv: Option<Vec<ScoreVector>>,
+    /// Raw predicted vectors behind `abs`
+    abs_raw: Option<Vec<PredictionVector>>,
+    /// Raw predicted vectors behind `drv`
+    drv_raw: Option<Vec<PredictionVector>>,
+    /// Standard deviation in nT of the `i` component of the last prediction, see
+    /// `SECS::prediction_std`
+    std: Option<Vec<f64>>,
+    /// Locations of the observations of the fit behind the last prediction
+    stations: Vec<GeographicalPoint>,
+    /// Grid the predictions were made on
+    grid: GridDefinition,
+}
+
+/// What the fit behind a prediction tells about how far it can be trusted
+#[derive(Clone, Default)]
+struct Reliability {
+    /// See `HemispherePredictions::std`
+    std: Option<Vec<f64>>,
+    /// See `HemispherePredictions::stations`
+    stations: Vec<GeographicalPoint>,
+}
+
+#[derive(Clone)]
+struct PredictionStorage {
+    /// Predictions of each hemisphere, iterated in the order of the encoded scores
+    hemispheres: BTreeMap<Hemisphere, HemispherePredictions>,
+    /// Scores of every hemisphere combined and encoded one after the other, refreshed on every
+    /// store
+    encoded: Vec<u16>,
+    /// Time of the last store in nanoseconds since the epoch
+    timestamp: u64,
+}
+
+/// A named model with its own configuration, fits, transfer matrices and predictions
+struct ModelInstance {
+    config: ModelConfig,
+    secs: BTreeMap<Hemisphere, SECS>,
+    predictions: PredictionStorage,
+}
+
+/// Id of the model used by endpoints given no model id, its prediction grids also hold the
+/// composite map of all models
+const DEFAULT_MODEL: &str = "default";
+
+/// Grid of the scores of a hemisphere
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
+pub struct ScoresGrid {
+    pub hemisphere: Hemisphere,
+    pub grid: GridDefinition,
+}
+
+/// Describes the latest encoded scores so clients can decode them
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone)]
+pub struct ScoresMetadata {
+    /// Time of the last prediction in nanoseconds since the epoch
+    pub timestamp: u64,
+    /// Grids the scores are laid on, in the order the scores of each hemisphere are concatenated
+    pub grids: Vec<ScoresGrid>,
+    /// Version of the model (crate version)
+    pub model_version: String,
+    /// Whether the derivative scores were included
+    pub has_derivative: bool,
 }
 
 // MARK: Storage
-// storage in heap memory since it is not sensitive and can suffer from a refresh on install
+// storage in heap memory since it is not sensitive and can suffer from a refresh on install, the
+// roles and alert subscriptions being saved across upgrades by `pre_upgrade`
 thread_local! {
-    static STORED_SECS: RefCell<Option<SECS>> = RefCell::new(None);
-    static PREDICTIONS: RefCell<PredictionStorage> = RefCell::new(PredictionStorage { abs: None, drv: None });
-    static AUTHORIZED_USERS: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
+    static MODELS: RefCell<BTreeMap<String, ModelInstance>> = RefCell::new(BTreeMap::from([(
+        DEFAULT_MODEL.to_string(),
+        ModelInstance::new(ModelConfig::default()),
+    )]));
+    /// Composite of the predictions of every model, the certified and publicly served map
+    static PREDICTIONS: RefCell<PredictionStorage> = const { RefCell::new(PredictionStorage::empty()) };
+    static CERTIFIED_SCORES: RefCell<CertifiedTree> = const { RefCell::new(CertifiedTree::new()) };
+    static JOBS: RefCell<JobsState> = RefCell::new(JobsState::default());
+    static K_INDEX: RefCell<KIndexState> = RefCell::new(KIndexState::default());
+    static ALERTS: RefCell<AlertsState> = RefCell::new(AlertsState::default());
+    static SCORING: RefCell<ScoringProfile> = RefCell::new(ScoringProfile::default());
+    /// Latest cloud cover uploaded by a feeder
+    static CLOUDS: RefCell<Option<CloudCover>> = const { RefCell::new(None) };
+    static JOB_TIMERS: RefCell<Vec<TimerId>> = const { RefCell::new(vec![]) };
+    static AUTHORIZED_USERS: RefCell<HashMap<Principal, HashSet<Role>>> = RefCell::new(HashMap::new());
+}
+
+impl HemispherePredictions {
+    fn new(grid: GridDefinition) -> Self {
+        HemispherePredictions {
+            abs: None,
+            drv: None,
+            abs_raw: None,
+            drv_raw: None,
+            std: None,
+            stations: vec![],
+            grid,
+        }
+    }
+
+    /// Max of the absolute and derivative scores (or whichever of them was predicted) encoded with
+    /// the derivative bits telling which one drove each score
+    fn encode(&self) -> Vec<u16> {
+        match (&self.abs, &self.drv, &self.drv_raw) {
+            (abs, Some(drv), Some(drv_raw)) => abs
+                .clone()
+                // without absolute predictions the derivative drives every score
+                .unwrap_or_else(|| {
+                    drv.iter()
+                        .map(|v| ScoreVector { score: 0.0, ..*v })
+                        .collect()
+                })
+                .encode_with_derivative(drv, drv_raw),
+            (Some(abs), _, _) => abs.clone().encode(),
+            _ => vec![],
+        }
+    }
 }
 
 impl PredictionStorage {
-    pub fn load() -> Self {
-        PREDICTIONS.with(|p| p.borrow().clone())
-    }
-
-    /// Store the given data either in `.abs` or `.drv` depending on `is_derivative`
-    pub fn store(data: Vec<ScoreVector>, is_derivative: bool) {
-        let buf = PREDICTIONS.with(|p| p.borrow().clone());
-
-        PREDICTIONS.with(|p| {
-            if is_derivative {
-                *p.borrow_mut() = PredictionStorage {
-                    abs: buf.abs,
-                    drv: Some(data),
-                };
-            } else {
-                *p.borrow_mut() = PredictionStorage {
-                    drv: buf.drv,
-                    abs: Some(data),
-                };
-            }
-        });
+    const fn empty() -> Self {
+        PredictionStorage {
+            hemispheres: BTreeMap::new(),
+            encoded: vec![],
+            timestamp: 0,
+        }
+    }
+
+    /// Store the given data of the hemisphere either in `.abs` or `.drv` depending on
+    /// `is_derivative` and refresh the encoded scores
+    pub fn store(
+        &mut self,
+        hemisphere: Hemisphere,
+        raw: Vec<PredictionVector>,
+        data: Vec<ScoreVector>,
+        reliability: Reliability,
+        is_derivative: bool,
+        grid: GridDefinition,
+    ) {
+        let predictions = self
+            .hemispheres
+            .entry(hemisphere)
+            .or_insert_with(|| HemispherePredictions::new(grid));
+        if predictions.grid != grid {
+            // predictions made on another grid cannot be combined with the new ones
+            *predictions = HemispherePredictions::new(grid);
+        }
+        if is_derivative {
+            predictions.drv = Some(data);
+            predictions.drv_raw = Some(raw);
+        } else {
+            predictions.abs = Some(data);
+            predictions.abs_raw = Some(raw);
+        }
+        predictions.std = reliability.std;
+        predictions.stations = reliability.stations;
+        self.refresh();
+    }
+
+    /// Drop the predictions of a hemisphere that is no longer modelled
+    pub fn remove(&mut self, hemisphere: Hemisphere) {
+        if self.hemispheres.remove(&hemisphere).is_some() {
+            self.refresh();
+        }
+    }
+
+    /// Encode the scores of every hemisphere
+    fn refresh(&mut self) {
+        self.encoded = self.hemispheres.values().flat_map(|h| h.encode()).collect();
+        self.timestamp = ic_cdk::api::time();
+    }
+
+    /// Raw predicted vectors of every hemisphere, in the order of the encoded scores
+    fn raw(&self, is_derivative: bool) -> Vec<PredictionVector> {
+        self.hemispheres
+            .values()
+            .filter_map(|h| {
+                if is_derivative {
+                    h.drv_raw.clone()
+                } else {
+                    h.abs_raw.clone()
+                }
+            })
+            .flatten()
+            .collect()
+    }
+
+    /// Standard deviations of the last prediction of every hemisphere, in the order of the
+    /// encoded scores
+    fn std(&self) -> Vec<f64> {
+        self.hemispheres
+            .values()
+            .filter_map(|h| h.std.clone())
+            .flatten()
+            .collect()
+    }
+
+    /// Coverage of the points of every hemisphere by the stations of its last prediction, in the
+    /// order of the encoded scores
+    fn coverage(&self, radius: f64) -> Vec<Coverage> {
+        self.hemispheres
+            .values()
+            .flat_map(|h| StationIndex::new(&h.stations).coverages(&h.grid.points(), radius))
+            .collect()
+    }
+
+    /// Activity of the last absolute prediction of the hemisphere, quiet until a first one
+    fn activity(&self, hemisphere: Hemisphere, timestamp: u64) -> OvalActivity {
+        let raw = self
+            .hemispheres
+            .get(&hemisphere)
+            .and_then(|h| h.abs_raw.as_deref())
+            .unwrap_or_default();
+        OvalActivity::from_prediction(raw, timestamp)
+    }
+
+    /// Encoded scores in the self-describing binary container, `None` until a first prediction
+    fn container(&self) -> Option<ScoresContainer> {
+        let metadata = self.metadata()?;
+        let mut offset = 0;
+        let sections = metadata
+            .grids
+            .into_iter()
+            .map(|g| {
+                let scores = self.encoded[offset..offset + g.grid.size()].to_vec();
+                offset += g.grid.size();
+                ScoresSection {
+                    hemisphere: g.hemisphere,
+                    grid: g.grid,
+                    scores,
+                }
+            })
+            .collect();
+
+        Some(ScoresContainer::new(
+            metadata.timestamp,
+            metadata.has_derivative,
+            sections,
+        ))
+    }
+
+    fn metadata(&self) -> Option<ScoresMetadata> {
+        if self.hemispheres.is_empty() {
+            return None;
+        }
+
+        Some(ScoresMetadata {
+            timestamp: self.timestamp,
+            grids: self
+                .hemispheres
+                .iter()
+                .map(|(hemisphere, h)| ScoresGrid {
+                    hemisphere: *hemisphere,
+                    grid: h.grid,
+                })
+                .collect(),
+            model_version: env!("CARGO_PKG_VERSION").to_string(),
+            has_derivative: self.hemispheres.values().any(|h| h.drv.is_some()),
+        })
     }
 }
 
-impl SECS {
-    pub fn load() -> Self {
-        STORED_SECS.with(|p| p.borrow().clone()).unwrap_or_default()
+impl ModelInstance {
+    fn new(config: ModelConfig) -> Self {
+        ModelInstance {
+            config,
+            secs: BTreeMap::new(),
+            predictions: PredictionStorage::empty(),
+        }
     }
 
-    pub fn store(self) {
-        STORED_SECS.with(|p| {
-            *p.borrow_mut() = Some(self);
-        });
+    /// Run `f` on the model, trapping when it does not exist
+    fn with<R>(model: &str, f: impl FnOnce(&mut ModelInstance) -> R) -> R {
+        MODELS.with(|m| match m.borrow_mut().get_mut(model) {
+            Some(instance) => f(instance),
+            None => ic_cdk::trap(&format!("Unknown model: {}", model)),
+        })
     }
 
-    pub fn clear() {
-        STORED_SECS.with(|p| {
-            *p.borrow_mut() = None;
-        });
+    /// Ids of every model
+    fn ids() -> Vec<String> {
+        MODELS.with(|m| m.borrow().keys().cloned().collect())
+    }
+}
+
+/// Merge the predictions of every model onto the prediction grids of the default model and
+/// certify the resulting map along with its metadata
+fn update_composite() {
+    let mut composite = MODELS.with(|m| {
+        let m = m.borrow();
+        let targets: Vec<(Hemisphere, GridDefinition)> = m
+            .get(DEFAULT_MODEL)
+            .map(|default| {
+                let config = &default.config;
+                config
+                    .hemispheres()
+                    .into_iter()
+                    .filter_map(|h| Some((h, config.grids(h)?.pred_grid)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let models: Vec<&PredictionStorage> = m.values().map(|i| &i.predictions).collect();
+
+        composite::composite(&targets, &models)
+    });
+    composite.refresh();
+
+    let mut tree = match composite.metadata() {
+        Some(metadata) => certified_tree(&composite.encoded, &metadata),
+        None => CertifiedTree::new(),
+    };
+    for (path, body) in http::certified_bodies(&composite) {
+        tree.certify_response(path, &body);
+    }
+    ic_cdk::api::set_certified_data(&tree.root_hash());
+    CERTIFIED_SCORES.with(|t| *t.borrow_mut() = tree);
+    PREDICTIONS.with(|p| *p.borrow_mut() = composite);
+}
+
+/// Model id given to an endpoint, the default model when none is given
+fn model_id(model: Option<String>) -> String {
+    model.unwrap_or_else(|| DEFAULT_MODEL.to_string())
+}
+
+impl SECS {
+    /// Run `f` on the SECs of the hemisphere of the model in place, their transfer matrices being
+    /// too large to be copied. The SECs are created from the configuration when none were stored
+    /// yet, `None` is returned when the hemisphere is not modelled.
+    pub fn with<R>(
+        model: &str,
+        hemisphere: Hemisphere,
+        f: impl FnOnce(&mut SECS) -> R,
+    ) -> Option<R> {
+        ModelInstance::with(model, |m| {
+            let grids = m.config.grids(hemisphere)?;
+            let secs = m
+                .secs
+                .entry(hemisphere)
+                .or_insert_with(|| SECS::new(grids.sec_grid.points(), m.config.sec_altitude));
+            Some(f(secs))
+        })
+    }
+
+    /// Run `f` on the stored SECs of the hemisphere of the model in place, `None` when none were
+    /// stored
+    pub fn with_stored<R>(
+        model: &str,
+        hemisphere: Hemisphere,
+        f: impl FnOnce(&mut SECS) -> R,
+    ) -> Option<R> {
+        ModelInstance::with(model, |m| m.secs.get_mut(&hemisphere).map(f))
     }
 }
 
 // MARK: Authorization calls
 
-fn is_authorized() -> bool {
-    let caller = caller();
+/// Permission groups a principal can be granted, each endpoint requires exactly one of them
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    /// Manages users, roles and configuration
+    Admin,
+    /// Submits observations (`m_fit_obs`) and cloud cover (`m_set_clouds`)
+    Feeder,
+    /// Triggers recomputations (`m_fit_pred`, `m_predict`)
+    Operator,
+    /// Fetches scores and predictions
+    Reader,
+}
+
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct UserRoles {
+    pub user: Principal,
+    pub roles: Vec<Role>,
+}
+
+/// Arguments given on canister installation (and re-applied on upgrade, on top of the state saved
+/// by `pre_upgrade`)
+#[derive(CandidType, Deserialize)]
+pub struct InitArgs {
+    /// Principals granted the `Admin` role right after deployment
+    pub admins: Vec<Principal>,
+    /// Configuration of the default model, defaults to `ModelConfig::default()`
+    pub config: Option<ModelConfig>,
+    /// Named models created next to the default one
+    pub models: Option<Vec<ModelDefinition>>,
+    /// Recomputation jobs configuration, defaults to `JobsConfig::default()`
+    pub jobs: Option<JobsConfig>,
+    /// Virtual stations followed for the local K indices, defaults to the Kp network
+    pub stations: Option<Vec<VirtualStation>>,
+    /// Curves scoring the predictions, defaults to `ScoringProfile::default()`
+    pub scoring: Option<ScoringProfile>,
+}
+
+#[ic_cdk::init]
+fn init(args: InitArgs) {
+    for admin in args.admins {
+        grant_role(admin, Role::Admin);
+    }
+
+    if let Some(config) = args.config {
+        set_config(DEFAULT_MODEL, config);
+    }
+    for model in args.models.unwrap_or_default() {
+        set_config(&model.id, model.config);
+    }
+
+    set_jobs_config(args.jobs.unwrap_or_default());
+    if let Some(stations) = args.stations {
+        set_stations(stations);
+    }
+    if let Some(scoring) = args.scoring {
+        set_scoring(scoring);
+    }
+    // certify the http responses before the first prediction
+    update_composite();
+}
+
+/// State saved to stable memory across upgrades, the rest of the heap being rebuilt from the
+/// init arguments and the next fits
+#[derive(CandidType, Deserialize)]
+struct UpgradeState {
+    /// Roles granted at runtime with `a_grant_role`
+    users: Option<HashMap<Principal, HashSet<Role>>>,
+    /// Subscriptions and pending events of the alert subscribers
+    alerts: Option<AlertsState>,
+}
+
+fn saved_state() -> UpgradeState {
+    UpgradeState {
+        users: Some(AUTHORIZED_USERS.with(|users| users.borrow().clone())),
+        alerts: Some(ALERTS.with(|alerts| alerts.borrow().clone())),
+    }
+}
 
-    if caller == Principal::anonymous() {
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    if let Err(e) = ic_cdk::storage::stable_save((saved_state(),)) {
+        ic_cdk::trap(&format!("Failed to save the state: {}", e));
+    }
+}
+
+#[ic_cdk::post_upgrade]
+fn post_upgrade(args: InitArgs) {
+    // nothing was saved when upgrading from a version without `pre_upgrade`
+    if let Ok((state,)) = ic_cdk::storage::stable_restore::<(UpgradeState,)>() {
+        restore(state);
+    }
+    init(args);
+}
+
+fn restore(state: UpgradeState) {
+    if let Some(users) = state.users {
+        AUTHORIZED_USERS.with(|u| *u.borrow_mut() = users);
+    }
+    if let Some(alerts) = state.alerts {
+        ALERTS.with(|a| *a.borrow_mut() = alerts);
+    }
+}
+
+fn grant_role(user: Principal, role: Role) {
+    if user == Principal::anonymous() {
+        return;
+    }
+
+    AUTHORIZED_USERS.with(|users| {
+        users.borrow_mut().entry(user).or_default().insert(role);
+    });
+}
+
+fn has_role(user: &Principal, role: Role) -> bool {
+    if *user == Principal::anonymous() {
         return false;
     }
 
-    AUTHORIZED_USERS.with(|users| users.borrow().contains(&caller))
+    AUTHORIZED_USERS.with(|users| {
+        users
+            .borrow()
+            .get(user)
+            .is_some_and(|roles| roles.contains(&role))
+    })
 }
 
-fn require_authorization() {
-    if !is_authorized() {
-        ic_cdk::trap("Access denied: caller not authorized");
+/// Traps unless the caller holds `role`, controllers of the canister always pass the `Admin`
+/// check so access can be recovered even when no admin is left
+fn require_role(role: Role) {
+    let caller = caller();
+
+    if has_role(&caller, role) || (role == Role::Admin && ic_cdk::api::is_controller(&caller)) {
+        return;
     }
+
+    ic_cdk::trap(&format!(
+        "Access denied: caller is missing the {:?} role",
+        role
+    ));
 }
 
 // prefix a_ for authorization
 
 #[ic_cdk::update]
-pub fn a_add_authorized_user(user_principal: Principal) {
-    require_authorization();
+pub fn a_grant_role(user: Principal, role: Role) {
+    require_role(Role::Admin);
+    grant_role(user, role);
+}
+
+#[ic_cdk::update]
+pub fn a_revoke_role(user: Principal, role: Role) {
+    require_role(Role::Admin);
     AUTHORIZED_USERS.with(|users| {
-        users.borrow_mut().insert(user_principal);
+        let mut users = users.borrow_mut();
+        if let Some(roles) = users.get_mut(&user) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                users.remove(&user);
+            }
+        }
     });
 }
 
+/// Revoke every role of the given user
 #[ic_cdk::update]
-pub fn a_remove_authorized_user(user_principal: Principal) {
-    require_authorization();
+pub fn a_remove_user(user: Principal) {
+    require_role(Role::Admin);
     AUTHORIZED_USERS.with(|users| {
-        users.borrow_mut().remove(&user_principal);
+        users.borrow_mut().remove(&user);
     });
 }
 
 #[ic_cdk::query]
-pub fn a_list_authorized_users() -> Vec<Principal> {
-    require_authorization();
-    AUTHORIZED_USERS.with(|users| users.borrow().iter().cloned().collect())
+pub fn a_list_users() -> Vec<UserRoles> {
+    require_role(Role::Admin);
+    AUTHORIZED_USERS.with(|users| {
+        users
+            .borrow()
+            .iter()
+            .map(|(user, roles)| UserRoles {
+                user: *user,
+                roles: roles.iter().cloned().collect(),
+            })
+            .collect()
+    })
 }
 
-// Initialize function to add the first authorized user (call this after deployment)
-#[ic_cdk::update]
-pub fn a_initialize_authorized_user(user_principal: Principal) {
-    // only allow if no users are authorized yet
-    AUTHORIZED_USERS.with(|users| {
-        if users.borrow().is_empty() {
-            users.borrow_mut().insert(user_principal);
-        } else {
-            ic_cdk::trap("Authorized users already exist");
+// MARK: Configuration calls
+// Every model has its own configuration, calls given no model id address the default model
+// prefix c_ for configuration
+
+impl ModelConfig {
+    pub fn load(model: &str) -> Self {
+        ModelInstance::with(model, |m| m.config.clone())
+    }
+}
+
+/// Validate and apply the configuration of the model, creating it when it does not exist and
+/// dropping the cached transfer matrices it invalidates
+fn set_config(model: &str, config: ModelConfig) {
+    if let Err(e) = validate_model_id(model) {
+        ic_cdk::trap(&format!("Invalid configuration: {}", e));
+    }
+    let other_t_pred_elements = MODELS.with(|m| {
+        m.borrow()
+            .iter()
+            .filter(|(id, _)| *id != model)
+            .map(|(_, i)| i.config.t_pred_elements())
+            .sum()
+    });
+    if let Err(e) = config.validate(other_t_pred_elements) {
+        ic_cdk::trap(&format!("Invalid configuration: {}", e));
+    }
+
+    MODELS.with(|m| {
+        let mut m = m.borrow_mut();
+        let instance = m
+            .entry(model.to_string())
+            .or_insert_with(|| ModelInstance::new(config.clone()));
+        let previous = std::mem::replace(&mut instance.config, config.clone());
+        for hemisphere in Hemisphere::ALL {
+            if config.grids(hemisphere).is_none() {
+                instance.secs.remove(&hemisphere);
+                JOBS.with(|j| j.borrow_mut().clear_pending(model, hemisphere));
+                instance.predictions.remove(hemisphere);
+            } else if config.invalidates_secs(&previous, hemisphere) {
+                instance.secs.remove(&hemisphere);
+                // amplitudes of pending fits belong to the previous SECs
+                JOBS.with(|j| j.borrow_mut().clear_pending(model, hemisphere));
+            } else if config.invalidates_t_pred(&previous, hemisphere) {
+                if let Some(secs) = instance.secs.get_mut(&hemisphere) {
+                    secs.clear_t_pred();
+                }
+            }
         }
     });
+    update_composite();
+}
+
+/// Create a named model, its scores are merged into the composite map once predicted
+#[ic_cdk::update]
+pub fn c_create_model(id: String, config: ModelConfig) {
+    require_role(Role::Admin);
+    if MODELS.with(|m| m.borrow().contains_key(&id)) {
+        ic_cdk::trap(&format!("Model {} already exists", id));
+    }
+    set_config(&id, config);
+}
+
+/// Remove a named model along with its fits and predictions, the default model cannot be removed
+#[ic_cdk::update]
+pub fn c_remove_model(id: String) {
+    require_role(Role::Admin);
+    if id == DEFAULT_MODEL {
+        ic_cdk::trap("The default model cannot be removed");
+    }
+    if MODELS.with(|m| m.borrow_mut().remove(&id)).is_none() {
+        ic_cdk::trap(&format!("Unknown model: {}", id));
+    }
+    for hemisphere in Hemisphere::ALL {
+        JOBS.with(|j| j.borrow_mut().clear_pending(&id, hemisphere));
+    }
+    update_composite();
+}
+
+#[ic_cdk::query]
+pub fn c_list_models() -> Vec<ModelDefinition> {
+    MODELS.with(|m| {
+        m.borrow()
+            .iter()
+            .map(|(id, i)| ModelDefinition {
+                id: id.clone(),
+                config: i.config.clone(),
+            })
+            .collect()
+    })
+}
+
+#[ic_cdk::update]
+pub fn c_set_config(config: ModelConfig, model: Option<String>) {
+    require_role(Role::Admin);
+    let model = model_id(model);
+    // checked beforehand since `set_config` creates missing models
+    ModelInstance::with(&model, |_| ());
+    set_config(&model, config);
+}
+
+#[ic_cdk::query]
+pub fn c_get_config(model: Option<String>) -> ModelConfig {
+    ModelConfig::load(&model_id(model))
+}
+
+/// Validate and apply the scoring profile, used from the next prediction on
+fn set_scoring(profile: ScoringProfile) {
+    if let Err(e) = profile.validate() {
+        ic_cdk::trap(&format!("Invalid scoring profile: {}", e));
+    }
+
+    SCORING.with(|s| *s.borrow_mut() = profile);
+}
+
+#[ic_cdk::update]
+pub fn c_set_scoring(profile: ScoringProfile) {
+    require_role(Role::Admin);
+    set_scoring(profile);
+}
+
+#[ic_cdk::query]
+pub fn c_get_scoring() -> ScoringProfile {
+    SCORING.with(|s| s.borrow().clone())
 }
 
 // MARK: Model calls
-// Requiring authorization on all update calls since we rely on the memory set after each
+// Requiring a role on all update calls since we rely on the memory set after each
 // prefix m_ for model
 
+/// Instructions `m_fit_pred` may use before yielding, leaves room under the 40B per message limit
+/// for storing the matrix
+const FIT_PRED_INSTRUCTION_BUDGET: u64 = 20_000_000_000;
+/// Prediction locations computed between two checks of the instruction counter
+const FIT_PRED_CHUNK_ROWS: usize = 100;
+
+/// Ids of the given model, or of every model when none is given
+fn model_ids(model: Option<String>) -> Vec<String> {
+    match model {
+        Some(model) => {
+            ModelInstance::with(&model, |_| ());
+            vec![model]
+        }
+        None => ModelInstance::ids(),
+    }
+}
+
+/// Replace the cloud cover faded into the scores by `q_visibility`
+#[ic_cdk::update]
+pub fn m_set_clouds(cover: CloudCover) {
+    require_role(Role::Feeder);
+    if let Err(e) = cover.validate() {
+        ic_cdk::trap(&format!("Invalid cloud cover: {}", e));
+    }
+
+    CLOUDS.with(|c| *c.borrow_mut() = Some(cover));
+}
+
 // Returns whether fiting predictions is neccesary
+// Observations are fitted by the given model (the default one when `None`), in the model of
+// their hemisphere, those of a hemisphere that is not modelled are ignored
+// When `is_derivative` is given the fit is queued for the prediction job, otherwise `m_predict`
+// has to be called
+#[ic_cdk::update]
+pub fn m_fit_obs(
+    obs: Vec<ObservationVector>,
+    is_derivative: Option<bool>,
+    model: Option<String>,
+) -> bool {
+    require_role(Role::Feeder);
+    if let Err(e) = validate_observations(&obs) {
+        ic_cdk::trap(&format!("Invalid observations: {}", e));
+    }
+
+    let model = model_id(model);
+    let config = ModelConfig::load(&model);
+    let mut needs_pred_fit = false;
+    for hemisphere in config.hemispheres() {
+        let obs_zero_k: Vec<ObservationVector> = obs
+            .iter()
+            .filter(|o| Hemisphere::of(o.lat) == hemisphere)
+            .map(|o| ObservationVector { k: 0.0, ..*o })
+            .collect();
+        if obs_zero_k.is_empty() {
+            continue;
+        }
+
+        let Some((pred_fit_missing, sec_amps)) = SECS::with(&model, hemisphere, |secs| {
+            secs.fit(&obs_zero_k, 0.0, config.epsilon);
+            let std_pending = std_priors(&config, secs)
+                .is_some_and(|(sigmas, field_sigma)| secs.std_pending(&sigmas, field_sigma));
+            (
+                secs.t_pred_cache.is_none() || std_pending,
+                secs.sec_amps.clone(),
+            )
+        }) else {
+            continue;
+        };
+        needs_pred_fit |= pred_fit_missing;
+        if let (Some(is_derivative), Some(sec_amps)) = (is_derivative, sec_amps) {
+            JOBS.with(|j| {
+                j.borrow_mut().queue(PendingFit {
+                    model: model.clone(),
+                    hemisphere,
+                    is_derivative,
+                    sec_amps,
+                    fitted_at: ic_cdk::api::time(),
+                    attempts: 0,
+                })
+            });
+        }
+    }
+
+    needs_pred_fit
+}
+
+/// Computes the prediction transfer matrices of the fitted hemispheres of the given model (of
+/// every model when `None`) a chunk of rows at a time, one hemisphere after the other, stopping
+/// before reaching the instruction limit of the message. Call again until it returns `Ready`
+/// (`Missing` before any fit), progress is kept between calls. The standard deviations of the
+/// predictions are computed next when needed (see `std_priors`), the fit job completing them if
+/// this call runs out of instructions.
+#[ic_cdk::update]
+pub fn m_fit_pred(model: Option<String>) -> TPredStatus {
+    require_role(Role::Operator);
+    fit_pred(&model_ids(model))
+}
+
+fn fit_pred(models: &[String]) -> TPredStatus {
+    for model in models {
+        let config = ModelConfig::load(model);
+        for hemisphere in config.hemispheres() {
+            if ic_cdk::api::instruction_counter() >= FIT_PRED_INSTRUCTION_BUDGET {
+                break;
+            }
+            let Some(grids) = config.grids(hemisphere) else {
+                continue;
+            };
+
+            // hemispheres not fitted yet are left without a matrix
+            SECS::with_stored(model, hemisphere, |secs| {
+                secs.t_pred_storage = config.t_pred_storage.unwrap_or_default();
+                let mut status = secs.start_t_pred(&grids.pred_grid.points(), config.pred_altitude);
+                while matches!(status, TPredStatus::Building { .. })
+                    && ic_cdk::api::instruction_counter() < FIT_PRED_INSTRUCTION_BUDGET
+                {
+                    status = secs.step_t_pred(FIT_PRED_CHUNK_ROWS);
+                }
+
+                if let Some((sigmas, field_sigma)) = std_priors(&config, secs) {
+                    while secs.std_pending(&sigmas, field_sigma)
+                        && ic_cdk::api::instruction_counter() < FIT_PRED_INSTRUCTION_BUDGET
+                    {
+                        secs.step_std(&sigmas, field_sigma, FIT_PRED_CHUNK_ROWS);
+                    }
+                }
+            });
+        }
+    }
+
+    t_pred_status(models)
+}
+
+/// Noise levels of the observations of the last fit and standard deviation of the field the
+/// uncertainty of the predictions is estimated from, `None` when neither the model configures
+/// priors for `q_uncertainty` nor the scoring profile fades the scores with it
+fn std_priors(config: &ModelConfig, secs: &SECS) -> Option<(Vec<f64>, f64)> {
+    if config.uncertainty.is_none() && SCORING.with(|s| s.borrow().uncertainty.is_none()) {
+        return None;
+    }
+    let uncertainty = config.uncertainty.clone().unwrap_or_default();
+    Some((
+        uncertainty.sigmas(&secs.obs_locs_cache),
+        uncertainty.field_sigma,
+    ))
+}
+
+/// Whether a standard deviation of the predictions of the models is left to compute
+fn std_pending(models: &[String]) -> bool {
+    models.iter().any(|model| {
+        ModelInstance::with(model, |m| {
+            m.secs.values().any(|secs| {
+                std_priors(&m.config, secs)
+                    .is_some_and(|(sigmas, field_sigma)| secs.std_pending(&sigmas, field_sigma))
+            })
+        })
+    })
+}
+
+/// Progress of the prediction transfer matrices of the given model, of every model when `None`,
+/// the hemispheres not fitted yet being left out (see `j_status` for each hemisphere)
+#[ic_cdk::query]
+pub fn m_fit_pred_status(model: Option<String>) -> TPredStatus {
+    require_role(Role::Operator);
+    t_pred_status(&model_ids(model))
+}
+
+/// Bytes of heap memory taken by the prediction transfer matrices of the given model, of every
+/// model when `None`, as set by the `t_pred_storage` of their configuration
+#[ic_cdk::query]
+pub fn m_t_pred_memory(model: Option<String>) -> u64 {
+    require_role(Role::Operator);
+    model_ids(model)
+        .iter()
+        .map(|model| {
+            ModelInstance::with(model, |m| {
+                m.secs
+                    .values()
+                    .filter_map(|secs| secs.t_pred_cache.as_ref())
+                    .map(|t| t.bytes() as u64)
+                    .sum::<u64>()
+            })
+        })
+        .sum()
+}
+
+fn hemisphere_t_pred_status(model: &str, hemisphere: Hemisphere) -> TPredStatus {
+    ModelInstance::with(model, |m| {
+        m.secs
+            .get(&hemisphere)
+            .map_or(TPredStatus::Missing, |secs| secs.t_pred_status())
+    })
+}
+
+/// Progress of the prediction transfer matrix of every modelled hemisphere of the models
+fn hemisphere_t_pred_statuses(models: &[String]) -> Vec<HemisphereTPredStatus> {
+    models
+        .iter()
+        .flat_map(|model| {
+            ModelConfig::load(model)
+                .hemispheres()
+                .into_iter()
+                .map(|hemisphere| HemisphereTPredStatus {
+                    model: model.clone(),
+                    hemisphere,
+                    t_pred: hemisphere_t_pred_status(model, hemisphere),
+                })
+        })
+        .collect()
+}
+
+/// Progress of the prediction transfer matrices of the fitted hemispheres of the models
+fn t_pred_status(models: &[String]) -> TPredStatus {
+    hemisphere_t_pred_statuses(models)
+        .into_iter()
+        .map(|h| h.t_pred)
+        .fold(TPredStatus::Missing, TPredStatus::merge)
+}
+
+/// Predicts every hemisphere that was fitted of the given model (of every model when `None`),
+/// returning their predicted vectors one after the other
 #[ic_cdk::update]
-pub fn m_fit_obs(obs: Vec<ObservationVector>) -> bool {
-    require_authorization();
+pub fn m_predict(is_derivative: bool, model: Option<String>) -> Vec<PredictionVector> {
+    require_role(Role::Operator);
+
+    let mut predictions = vec![];
+    for model in model_ids(model) {
+        for hemisphere in ModelConfig::load(&model).hemispheres() {
+            // skipped until fitted and while its transfer matrix is being rebuilt
+            let Some(prediction) = SECS::with_stored(&model, hemisphere, |secs| {
+                (secs.sec_amps.is_some() && secs.t_pred_cache.is_some()).then(|| secs.predict())
+            })
+            .flatten() else {
+                continue;
+            };
+            predictions.extend(store_prediction(
+                &model,
+                hemisphere,
+                prediction,
+                is_derivative,
+                ic_cdk::api::time(),
+            ));
+        }
+    }
+    update_composite();
+    evaluate_alerts();
+    if !is_derivative {
+        record_k_samples();
+    }
+
+    predictions
+}
 
-    let mut secs: SECS = if STORED_SECS.with(|storage| storage.borrow().is_some()) {
-        SECS::load()
+/// Score the raw prediction of the hemisphere of the model and store both, the composite map has
+/// to be updated afterwards. The auroral oval and the darkness are those at `timestamp`, the
+/// time of the fit in nanoseconds since the epoch.
+fn store_prediction(
+    model: &str,
+    hemisphere: Hemisphere,
+    raw_prediction: Vec<PredictionVector>,
+    is_derivative: bool,
+    timestamp: u64,
+) -> Vec<PredictionVector> {
+    let profile = SCORING.with(|s| s.borrow().clone());
+    let prediction: Vec<ScoreVector> = if is_derivative {
+        raw_prediction.clone().into_derivative_scores(&profile)
     } else {
-        SECS::new(geographical_grid(45.0..85.0, 50, -170.0..35.0, 50), 110e3)
+        raw_prediction.clone().into_scores(&profile)
     };
 
-    let obs_zero_k: Vec<ObservationVector> = obs
+    ModelInstance::with(model, |m| {
+        let Some(grids) = m.config.grids(hemisphere) else {
+            ic_cdk::trap(&format!("The {:?} hemisphere is not modelled", hemisphere));
+        };
+        let pred_grid = grids.pred_grid;
+        // computed by the fit job, see `fit_pred`
+        let reliability = m
+            .secs
+            .get(&hemisphere)
+            .map(|secs| Reliability {
+                std: std_priors(&m.config, secs)
+                    .and_then(|(sigmas, field_sigma)| secs.prediction_std(&sigmas, field_sigma)),
+                stations: secs.obs_locs_cache.clone(),
+            })
+            .unwrap_or_default();
+        let coverage = match profile.coverage {
+            Some(_) => StationIndex::new(&reliability.stations)
+                .coverages(&pred_grid.points(), profile.coverage_radius()),
+            None => vec![],
+        };
+        // derivative predictions displace the oval as the last absolute one does
+        let activity = if is_derivative {
+            m.predictions.activity(hemisphere, timestamp)
+        } else {
+            OvalActivity::from_prediction(&raw_prediction, timestamp)
+        };
+        m.predictions.store(
+            hemisphere,
+            raw_prediction.clone(),
+            prediction
+                .ponderate_auroral_zone(&profile, &activity)
+                .ponderate_uncertainty(&profile, reliability.std.as_deref())
+                .ponderate_coverage(&profile, &coverage)
+                .line_of_sight(&profile, &pred_grid)
+                .ponderate_darkness(&profile, timestamp),
+            reliability,
+            is_derivative,
+            pred_grid,
+        );
+    });
+
+    raw_prediction
+}
+
+/// Prediction at a point requested to `m_point_forecast`
+#[derive(CandidType, Deserialize, Debug, Clone)]
+pub struct PointForecast {
+    pub prediction: PredictionVector,
+    /// Score of the prediction weighted as the scores of the grid by the auroral zone, the
+    /// coverage by the stations of the last fit and the darkness, overhead the point regardless
+    /// of the line of sight of the scoring profile. The uncertainty of the prediction is not
+    /// computed for arbitrary points, its weight is not applied.
+    pub score: f64,
+}
+
+/// Points forecast by a single call, all of their transfer rows fit in the cache
+const MAX_FORECAST_POINTS: usize = POINT_CACHE_SIZE;
+
+/// Predicts the given points from the last fit of the model (the default one when `None`) in
+/// their hemisphere, regardless of its prediction grid. `None` for the points of a hemisphere
+/// that was not fitted yet.
+#[ic_cdk::update]
+pub fn m_point_forecast(
+    points: Vec<GeographicalPoint>,
+    model: Option<String>,
+) -> Vec<Option<PointForecast>> {
+    require_role(Role::Reader);
+
+    if points.len() > MAX_FORECAST_POINTS {
+        ic_cdk::trap(&format!(
+            "Invalid points: at most {} points can be forecast at once",
+            MAX_FORECAST_POINTS
+        ));
+    }
+    if points
+        .iter()
+        .any(|p| !(-90.0..=90.0).contains(&p.lat) || !p.lon.is_finite())
+    {
+        ic_cdk::trap("Invalid points: latitudes must be within [-90, 90]");
+    }
+
+    let model = model_id(model);
+    let pred_altitude = ModelConfig::load(&model).pred_altitude;
+    let profile = SCORING.with(|s| s.borrow().clone());
+    let mut forecasts = vec![None; points.len()];
+    for hemisphere in Hemisphere::ALL {
+        let (indices, hemisphere_points): (Vec<usize>, Vec<GeographicalPoint>) = points
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| Hemisphere::of(p.lat) == hemisphere)
+            .map(|(idx, p)| (idx, *p))
+            .unzip();
+        if hemisphere_points.is_empty() {
+            continue;
+        }
+
+        let (predictions, coverage, activity) = ModelInstance::with(&model, |m| {
+            let activity = m.predictions.activity(hemisphere, ic_cdk::api::time());
+            let Some(secs) = m.secs.get_mut(&hemisphere) else {
+                return (None, vec![], activity);
+            };
+            // covered by the stations of the last fit, as the grid predicted from it
+            let coverage = match profile.coverage {
+                Some(_) => StationIndex::new(&secs.obs_locs_cache)
+                    .coverages(&hemisphere_points, profile.coverage_radius()),
+                None => vec![],
+            };
+            let predictions = secs.predict_points(&hemisphere_points, pred_altitude);
+            (predictions, coverage, activity)
+        });
+        let Some(predictions) = predictions else {
+            continue;
+        };
+        let scores = predictions
+            .clone()
+            .into_scores(&profile)
+            .ponderate_auroral_zone(&profile, &activity)
+            .ponderate_coverage(&profile, &coverage)
+            .ponderate_darkness(&profile, activity.timestamp);
+        for ((idx, prediction), score) in indices.into_iter().zip(predictions).zip(scores) {
+            forecasts[idx] = Some(PointForecast {
+                prediction,
+                score: score.score,
+            });
+        }
+    }
+
+    forecasts
+}
+
+/// Encoded scores of the given model, the composite map of all models when `None`
+#[ic_cdk::update]
+pub fn m_scores(model: Option<String>) -> Vec<u16> {
+    require_role(Role::Reader);
+
+    scores(model)
+}
+
+fn scores(model: Option<String>) -> Vec<u16> {
+    match model {
+        None => PREDICTIONS.with(|p| p.borrow().encoded.clone()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.encoded.clone()),
+    }
+}
+
+// MARK: Jobs
+// Timers replacing the external cron calling `m_fit_pred` and `m_predict`
+// prefix j_ for jobs
+
+/// Validate and apply the jobs configuration, restarting the timers
+fn set_jobs_config(config: JobsConfig) {
+    if let Err(e) = config.validate() {
+        ic_cdk::trap(&format!("Invalid jobs configuration: {}", e));
+    }
+
+    JOB_TIMERS.with(|t| {
+        t.borrow_mut()
+            .drain(..)
+            .for_each(ic_cdk_timers::clear_timer)
+    });
+    if config.enabled {
+        let timers = vec![
+            ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(config.fit_pred_interval_secs),
+                run_fit_pred_job,
+            ),
+            ic_cdk_timers::set_timer_interval(
+                Duration::from_secs(config.predict_interval_secs),
+                run_predict_job,
+            ),
+        ];
+        JOB_TIMERS.with(|t| *t.borrow_mut() = timers);
+    }
+    JOBS.with(|j| j.borrow_mut().config = config);
+}
+
+/// Continue the prediction transfer matrix and standard deviation computations of the models
+/// fitted at least once
+fn run_fit_pred_job() {
+    let fitted: Vec<String> = MODELS.with(|m| {
+        m.borrow()
+            .iter()
+            .filter(|(_, i)| !i.secs.is_empty())
+            .map(|(id, _)| id.clone())
+            .collect()
+    });
+    if !fitted.is_empty()
+        && (!matches!(t_pred_status(&fitted), TPredStatus::Ready { .. }) || std_pending(&fitted))
+    {
+        fit_pred(&fitted);
+    }
+}
+
+/// Predict and score the next pending fit of a hemisphere whose prediction transfer matrix is
+/// ready
+fn run_predict_job() {
+    let ready: Vec<(String, Hemisphere)> = ModelInstance::ids()
         .into_iter()
-        .map(|mut o| {
-            o.k = 0.0;
-            o
+        .flat_map(|model| {
+            ModelConfig::load(&model)
+                .hemispheres()
+                .into_iter()
+                .map(move |h| (model.clone(), h))
+        })
+        .filter(|(model, h)| {
+            matches!(
+                hemisphere_t_pred_status(model, *h),
+                TPredStatus::Ready { .. }
+            )
         })
         .collect();
-    secs.fit(&obs_zero_k, 0.0, 0.1);
-    let needs_pred_fit = secs.t_pred_cache.is_none();
-    secs.store();
-    needs_pred_fit
+    let Some(pending) = JOBS.with(|j| j.borrow_mut().next_pending(&ready)) else {
+        return;
+    };
+
+    // predicted in a message of its own: if it traps only the prediction is rolled back, the
+    // attempt counted by `next_pending` being kept
+    ic_cdk_timers::set_timer(Duration::ZERO, move || predict_pending(pending));
+}
+
+/// Predict and score a fit taken by `run_predict_job`, then remove it from the pending fits
+fn predict_pending(pending: PendingFit) {
+    // left pending when the transfer matrix started being rebuilt in the meantime
+    let Some(prediction) = SECS::with_stored(&pending.model, pending.hemisphere, |secs| {
+        secs.t_pred_cache
+            .is_some()
+            .then(|| secs.predict_with(&pending.sec_amps))
+    })
+    .flatten() else {
+        return;
+    };
+    JOBS.with(|j| j.borrow_mut().complete(&pending));
+    store_prediction(
+        &pending.model,
+        pending.hemisphere,
+        prediction,
+        pending.is_derivative,
+        pending.fitted_at,
+    );
+    update_composite();
+    evaluate_alerts();
+    if !pending.is_derivative {
+        record_k_samples();
+    }
+    JOBS.with(|j| {
+        let mut j = j.borrow_mut();
+        j.last_prediction = Some(ic_cdk::api::time());
+        j.predictions += 1;
+    });
 }
 
 #[ic_cdk::update]
-pub fn m_fit_pred() {
-    require_authorization();
+pub fn j_set_config(config: JobsConfig) {
+    require_role(Role::Admin);
+    set_jobs_config(config);
+}
+
+#[ic_cdk::query]
+pub fn j_status() -> JobsStatus {
+    require_role(Role::Operator);
+    let hemispheres = hemisphere_t_pred_statuses(&ModelInstance::ids());
+    JOBS.with(|j| j.borrow().status(hemispheres))
+}
+
+// MARK: K indices
+// Local K indices and Kp estimated from the absolute predictions of the composite map
+
+/// Validate and apply the virtual stations, dropping the windows recorded so far
+fn set_stations(stations: Vec<VirtualStation>) {
+    if let Err(e) = validate_stations(&stations) {
+        ic_cdk::trap(&format!("Invalid stations: {}", e));
+    }
 
-    let pred_grid = geographical_grid(45.0..85.0, 37, -180.0..179.0, 130);
-    let mut secs = SECS::load();
-    secs.calc_t_pred(&pred_grid, 110e3);
-    secs.store();
+    K_INDEX.with(|k| *k.borrow_mut() = KIndexState::new(stations));
+}
+
+/// Record the latest absolute prediction of the composite map at the virtual stations
+fn record_k_samples() {
+    PREDICTIONS.with(|p| {
+        let p = p.borrow();
+        K_INDEX.with(|k| {
+            k.borrow_mut().record(p.timestamp, |point| {
+                let h = p.hemispheres.get(&Hemisphere::of(point.lat))?;
+                let raw = h.abs_raw.as_ref()?;
+                let weights = composite::bilinear(&h.grid, point)?;
+                weights.iter().try_fold((0.0, 0.0), |(i, j), (idx, w)| {
+                    let pv = raw.get(*idx)?;
+                    Some((i + w * pv.i, j + w * pv.j))
+                })
+            })
+        })
+    });
 }
 
 #[ic_cdk::update]
-pub fn m_predict(is_derivative: bool) -> Vec<PredictionVector> {
-    require_authorization();
+pub fn c_set_stations(stations: Vec<VirtualStation>) {
+    require_role(Role::Admin);
+    set_stations(stations);
+}
 
-    let secs = SECS::load();
-    let raw_prediction: Vec<PredictionVector> = secs.predict();
-    let prediction: Vec<ScoreVector> = if is_derivative {
-        raw_prediction.clone().into_derivative_scores()
-    } else {
-        raw_prediction.clone().into_scores()
+#[ic_cdk::query]
+pub fn c_get_stations() -> Vec<VirtualStation> {
+    K_INDEX.with(|k| k.borrow().stations().to_vec())
+}
+
+/// Local K index of every virtual station over the current 3-hour window
+#[ic_cdk::query]
+pub fn q_local_k() -> Vec<StationK> {
+    K_INDEX.with(|k| k.borrow().local_k())
+}
+
+/// Kp estimated from the virtual stations of the Kp network, `None` until one was predicted
+#[ic_cdk::query]
+pub fn q_kp() -> Option<KpEstimate> {
+    K_INDEX.with(|k| k.borrow().kp())
+}
+
+// MARK: Subscriptions
+// Locations followed by readers, alerted in their outbox when the score of the composite map
+// crosses their threshold
+// prefix s_ for subscriptions
+
+/// Evaluate the subscriptions against the latest scores of the composite map
+fn evaluate_alerts() {
+    let Some(container) = PREDICTIONS.with(|p| p.borrow().container()) else {
+        return;
     };
+    ALERTS.with(|a| {
+        a.borrow_mut().evaluate(container.timestamp, |point| {
+            let section = container
+                .sections
+                .iter()
+                .find(|s| s.hemisphere == Hemisphere::of(point.lat))?;
+            let weights = composite::bilinear(&section.grid, point)?;
+            weights.iter().try_fold(0.0, |score, (idx, w)| {
+                Some(score + w * decode_score(*section.scores.get(*idx)?).score)
+            })
+        })
+    });
+}
+
+/// Follow the location, returning the id of the subscription
+#[ic_cdk::update]
+pub fn s_subscribe(location: GeographicalPoint, threshold: f64) -> u64 {
+    require_role(Role::Reader);
+    ALERTS
+        .with(|a| a.borrow_mut().subscribe(caller(), location, threshold))
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Invalid subscription: {}", e)))
+}
 
-    PredictionStorage::store(prediction.ponderate_auroral_zone(), is_derivative);
+/// Stop following a location, returns whether the subscription existed
+#[ic_cdk::update]
+pub fn s_unsubscribe(id: u64) -> bool {
+    require_role(Role::Reader);
+    ALERTS.with(|a| a.borrow_mut().unsubscribe(&caller(), id))
+}
 
-    raw_prediction
+#[ic_cdk::query]
+pub fn s_subscriptions() -> Vec<Subscription> {
+    require_role(Role::Reader);
+    ALERTS.with(|a| a.borrow().subscriptions(&caller()))
+}
+
+/// Alerts of the outbox of the caller following the `after` sequence number, all of them when
+/// `None`. They are kept until acknowledged with `s_ack_alerts`.
+#[ic_cdk::query]
+pub fn s_alerts(after: Option<u64>) -> Vec<AlertEvent> {
+    require_role(Role::Reader);
+    ALERTS.with(|a| a.borrow().events(&caller(), after))
 }
 
+/// Drop the alerts of the outbox of the caller up to the `seq` sequence number included
 #[ic_cdk::update]
-pub fn m_scores() -> Vec<u16> {
-    require_authorization();
+pub fn s_ack_alerts(seq: u64) {
+    require_role(Role::Reader);
+    ALERTS.with(|a| a.borrow_mut().acknowledge(&caller(), seq));
+}
+
+// MARK: Public calls
+// Served from the stored state without any recomputation, no authorization required. Given no
+// model id they serve the composite map of all models.
+// prefix q_ for query
+
+/// Latest encoded scores of every hemisphere, see `q_scores_metadata` for the grids they are
+/// laid on
+#[ic_cdk::query]
+pub fn q_scores(model: Option<String>) -> Vec<u16> {
+    scores(model)
+}
+
+/// Metadata of the latest scores, `None` until a first prediction is made
+#[ic_cdk::query]
+pub fn q_scores_metadata(model: Option<String>) -> Option<ScoresMetadata> {
+    match model {
+        None => PREDICTIONS.with(|p| p.borrow().metadata()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.metadata()),
+    }
+}
+
+/// Latest scores in the versioned binary container (see `ScoresContainer`), `None` until a first
+/// prediction is made
+#[ic_cdk::query]
+pub fn q_scores_container(model: Option<String>) -> Option<ByteBuf> {
+    let container = match model {
+        None => PREDICTIONS.with(|p| p.borrow().container()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.container()),
+    };
+    container.map(|c| ByteBuf::from(c.encode()))
+}
+
+/// Latest composite scores and metadata with the certificate and witness needed to verify them,
+/// `None` before the first prediction or when not called as a query
+#[ic_cdk::query]
+pub fn q_certified_scores() -> Option<CertifiedScores> {
+    let certificate = ic_cdk::api::data_certificate()?;
+
+    PREDICTIONS.with(|p| {
+        let p = p.borrow();
+        Some(CertifiedScores {
+            scores: p.encoded.clone(),
+            metadata: p.metadata()?,
+            certificate: ByteBuf::from(certificate),
+            witness: ByteBuf::from(CERTIFIED_SCORES.with(|t| witness(&t.borrow()))),
+        })
+    })
+}
+
+/// Equatorward edge of the aurora where the score reaches `threshold`, along with the line from
+/// which it stands on the horizon, for every hemisphere
+#[ic_cdk::query]
+pub fn q_view_lines(threshold: f64, model: Option<String>) -> Vec<ViewLine> {
+    if !(0.0..=10.0).contains(&threshold) {
+        ic_cdk::trap("Invalid threshold: must be between 0 and 10");
+    }
+
+    let container = match model {
+        None => PREDICTIONS.with(|p| p.borrow().container()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.container()),
+    };
+    container
+        .map(|c| viewline::view_lines(&c, threshold))
+        .unwrap_or_default()
+}
+
+/// Areas where the score reaches each of the thresholds (1 to 9 by default) for every hemisphere
+#[ic_cdk::query]
+pub fn q_contours(thresholds: Option<Vec<f64>>, model: Option<String>) -> Vec<Contour> {
+    let thresholds = thresholds.unwrap_or(DEFAULT_THRESHOLDS.to_vec());
+    if let Err(e) = validate_thresholds(&thresholds) {
+        ic_cdk::trap(&format!("Invalid thresholds: {}", e));
+    }
+
+    let container = match model {
+        None => PREDICTIONS.with(|p| p.borrow().container()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.container()),
+    };
+    container
+        .map(|c| contours::container_contours(&c, &thresholds))
+        .unwrap_or_default()
+}
+
+/// Standard deviation in nT of the `i` component of the latest prediction of every hemisphere,
+/// laid out as `q_predictions`. Only estimated for the models configuring `uncertainty` priors
+/// or when the scoring profile fades the scores with it, empty until the fit job computed it for
+/// the stations of the prediction.
+#[ic_cdk::query]
+pub fn q_uncertainty(model: Option<String>) -> Vec<f64> {
+    match model {
+        None => PREDICTIONS.with(|p| p.borrow().std()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.std()),
+    }
+}
+
+/// Distance to the nearest station and number of stations within `radius` km (that of the
+/// coverage policy of the scoring profile by default) of every point of the latest scores, laid
+/// out as the scores
+#[ic_cdk::query]
+pub fn q_coverage(radius: Option<f64>, model: Option<String>) -> Vec<Coverage> {
+    let radius = radius.unwrap_or_else(|| SCORING.with(|s| s.borrow().coverage_radius()));
+    if !(radius > 0.0 && radius <= 20_000.0) {
+        ic_cdk::trap("Invalid radius: must be within ]0, 20000] km");
+    }
+
+    match model {
+        None => PREDICTIONS.with(|p| p.borrow().coverage(radius)),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.coverage(radius)),
+    }
+}
 
-    let predictions = PredictionStorage::load();
-    match predictions.drv {
-        None => predictions.abs.unwrap_or_default().encode(),
-        Some(drv) => predictions
-            .abs
-            .unwrap_or_default()
-            .max_score_vectors(drv)
-            .encode(),
+/// Latest scores faded by the cloud cover (see `m_set_clouds`) as they stand now, `None` until
+/// a first prediction is made
+#[ic_cdk::query]
+pub fn q_visibility(model: Option<String>) -> Option<Visibility> {
+    let container = match model {
+        None => PREDICTIONS.with(|p| p.borrow().container()),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.container()),
+    }?;
+    CLOUDS.with(|c| {
+        Some(clouds::visibility(
+            &container,
+            c.borrow().as_ref(),
+            ic_cdk::api::time(),
+        ))
+    })
+}
+
+/// Raw predicted vectors of the latest absolute or derivative prediction of every hemisphere
+#[ic_cdk::query]
+pub fn q_predictions(is_derivative: bool, model: Option<String>) -> Vec<PredictionVector> {
+    match model {
+        None => PREDICTIONS.with(|p| p.borrow().raw(is_derivative)),
+        Some(model) => ModelInstance::with(&model, |m| m.predictions.raw(is_derivative)),
+    }
+}
+
+// MARK: HTTP gateway
+// Scores, metadata, health and predicted vectors of the composite map are certified for the
+// default gateway, the other responses are not and need the `raw` gateway domain, see
+// `http::CERTIFIED_PATHS`
+
+/// Serves the scores and predictions over HTTP. Only the responses for the composite map at
+/// `/`, `/health`, `/metadata`, `/scores`, `/scores.json`, `/scores.bin`, `/scores.nrls` and
+/// `/predictions.geojson` without any query string are certified. The others (`?model=` and
+/// `?derivative=` routes, view lines, contours) are only served unverified by the `raw.icp0.io`
+/// domain.
+#[ic_cdk::query]
+pub fn http_request(request: HttpRequest) -> HttpResponse {
+    let mut response =
+        PREDICTIONS.with(|p| MODELS.with(|m| http::handle(&request, &p.borrow(), &m.borrow())));
+    if let (Some(path), Some(certificate)) = (
+        http::certified_path(&request),
+        ic_cdk::api::data_certificate(),
+    ) {
+        response
+            .headers
+            .push(CERTIFIED_SCORES.with(|t| t.borrow().http_header(path, &certificate)));
     }
+    response
 }
 
 ic_cdk::export_candid!();
@@ -225,10 +1566,58 @@ mod tests {
     use ndarray::Array2;
 
     use super::*;
-    use crate::{geo::GeographicalPoint, model::PredictionVector};
-    use serde_json;
+    use crate::{
+        geo::{geographical_grid, GeographicalPoint},
+        model::PredictionVector,
+    };
     use std::fs;
 
+    /// Save the state as `pre_upgrade` does, wipe the heap and restore it as `post_upgrade` does
+    fn upgrade(state: UpgradeState) {
+        let bytes = candid::encode_one(state).unwrap();
+        AUTHORIZED_USERS.with(|u| u.borrow_mut().clear());
+        ALERTS.with(|a| *a.borrow_mut() = AlertsState::default());
+        restore(candid::decode_one(&bytes).unwrap());
+    }
+
+    #[test]
+    fn test_roles_survive_upgrade() {
+        let feeder = Principal::from_slice(&[7; 29]);
+        grant_role(feeder, Role::Feeder);
+
+        upgrade(saved_state());
+        assert!(has_role(&feeder, Role::Feeder));
+        assert!(!has_role(&feeder, Role::Admin));
+
+        // saved by a version without roles
+        upgrade(UpgradeState {
+            users: None,
+            alerts: None,
+        });
+        assert!(!has_role(&feeder, Role::Feeder));
+    }
+
+    #[test]
+    fn test_alerts_survive_upgrade() {
+        let subscriber = Principal::from_slice(&[8; 29]);
+        ALERTS.with(|a| {
+            let mut alerts = a.borrow_mut();
+            alerts
+                .subscribe(subscriber, GeographicalPoint::new(69.65, 18.96), 3.0)
+                .unwrap();
+            alerts.evaluate(1, |_| Some(4.0));
+        });
+        let saved = ALERTS.with(|a| a.borrow().clone());
+
+        upgrade(saved_state());
+        ALERTS.with(|a| {
+            let alerts = a.borrow();
+            assert_eq!(*alerts, saved);
+            assert!(alerts.subscriptions(&subscriber)[0].active);
+            assert_eq!(alerts.events(&subscriber, None).len(), 1);
+        });
+    }
+
     // #[test]
     // fn test_large_infer() {
     //     let mut secs = SECS::new(geographical_grid(45.0..85.0, 37, -170.0..35.0, 74), 0.0);
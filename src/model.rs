@@ -1,10 +1,15 @@
-use ndarray::{Array2, Array3, Axis};
-use ndarray_einsum::tensordot;
+use ndarray::Array2;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 use candid::CandidType;
 
-use crate::{geo::GeographicalPoint, svd::svd, t_df::t_df};
+use crate::{
+    geo::GeographicalPoint,
+    svd::svd,
+    t_df::t_df,
+    transfer::{TransferMatrix, TransferStorage},
+};
 
 // #[wasm_bindgen]
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone, Copy)]
@@ -21,6 +26,20 @@ pub struct ObservationVector {
     pub k: f64,
 }
 
+/// Observations must be finite, a single NaN would spread to every amplitude of the fit
+pub fn validate_observations(obs: &[ObservationVector]) -> Result<(), String> {
+    for o in obs {
+        if [o.lon, o.lat, o.i, o.j, o.k].iter().any(|v| !v.is_finite()) {
+            return Err(format!("non-finite observation at ({}, {})", o.lat, o.lon));
+        }
+        if !(-90.0..=90.0).contains(&o.lat) {
+            return Err(format!("invalid latitude {}", o.lat));
+        }
+    }
+
+    Ok(())
+}
+
 // #[wasm_bindgen]
 #[derive(CandidType, Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct PredictionVector {
@@ -36,6 +55,100 @@ pub struct PredictionVector {
     pub k: f64,
 }
 
+/// Progress of the prediction transfer matrix computation
+#[derive(CandidType, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum TPredStatus {
+    /// Neither computed nor being computed
+    Missing,
+    /// `done` out of `total` prediction locations have been computed
+    Building { done: u64, total: u64 },
+    /// Computed for `total` prediction locations
+    Ready { total: u64 },
+}
+
+impl TPredStatus {
+    /// Progress of two matrices taken together, `Ready` only once both are. A `Missing` matrix,
+    /// of a hemisphere not fitted yet, is left out.
+    pub fn merge(self, other: TPredStatus) -> TPredStatus {
+        let progress = |status: TPredStatus| match status {
+            TPredStatus::Missing => (0, 0),
+            TPredStatus::Building { done, total } => (done, total),
+            TPredStatus::Ready { total } => (total, total),
+        };
+
+        match (self, other) {
+            (TPredStatus::Missing, status) | (status, TPredStatus::Missing) => status,
+            (TPredStatus::Ready { total: a }, TPredStatus::Ready { total: b }) => {
+                TPredStatus::Ready { total: a + b }
+            }
+            (a, b) => {
+                let (a, b) = (progress(a), progress(b));
+                TPredStatus::Building {
+                    done: a.0 + b.0,
+                    total: a.1 + b.1,
+                }
+            }
+        }
+    }
+}
+
+/// Prediction transfer matrix being filled a chunk of prediction locations at a time
+#[derive(Debug, Clone)]
+pub struct TPredBuild {
+    pred_locs: Vec<GeographicalPoint>,
+    pred_altitude: f64,
+    t: TransferMatrix,
+    /// Index of the first prediction location left to compute
+    next: usize,
+}
+
+/// Points whose transfer rows are kept by a `PointTransferCache`
+pub const POINT_CACHE_SIZE: usize = 64;
+
+/// Transfer rows of arbitrary prediction points, outside of any prediction grid. Once full the
+/// least recently used rows are evicted.
+#[derive(Debug, Clone, Default)]
+pub struct PointTransferCache {
+    altitude: f64,
+    /// `[3][nsec]` rows keyed by the bits of the latitude and longitude of their point, along with
+    /// the tick of their last use
+    rows: BTreeMap<(u64, u64), (u64, Array2<f64>)>,
+    tick: u64,
+}
+
+impl PointTransferCache {
+    fn key(point: &GeographicalPoint) -> (u64, u64) {
+        (point.lat.to_bits(), point.lon.to_bits())
+    }
+
+    /// Row of the point if it is cached for the altitude, marking it as used
+    fn get(&mut self, point: &GeographicalPoint, altitude: f64) -> Option<Array2<f64>> {
+        if altitude != self.altitude {
+            return None;
+        }
+        self.tick += 1;
+        let (used, row) = self.rows.get_mut(&Self::key(point))?;
+        *used = self.tick;
+        Some(row.clone())
+    }
+
+    fn insert(&mut self, point: &GeographicalPoint, altitude: f64, row: Array2<f64>) {
+        if altitude != self.altitude {
+            self.rows.clear();
+            self.altitude = altitude;
+        }
+        if self.rows.len() >= POINT_CACHE_SIZE {
+            let oldest = self.rows.iter().min_by_key(|(_, (used, _))| *used);
+            if let Some(key) = oldest.map(|(key, _)| *key) {
+                self.rows.remove(&key);
+            }
+        }
+        self.tick += 1;
+        self.rows.insert(Self::key(point), (self.tick, row));
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Default)]
 pub struct SECS {
     /// The latitude and longiutde of the divergence free (df) SEC locations.
@@ -50,7 +163,22 @@ pub struct SECS {
     pub t_obs_flat_cache: Option<Array2<f64>>,
     /// The latitude, longiutde, and radius of the prediction locations.
     pub pred_locs_cache: Vec<GeographicalPoint>,
-    pub t_pred_cache: Option<Array3<f64>>,
+    pub t_pred_cache: Option<TransferMatrix>,
+    /// How `t_pred_cache` is stored, a different storage restarts its computation
+    pub t_pred_storage: TransferStorage,
+    /// Computation of `t_pred_cache` in progress, see `start_t_pred`
+    pub t_pred_build: Option<TPredBuild>,
+    /// Transfer rows of the points predicted by `predict_points`
+    pub point_cache: PointTransferCache,
+    /// Filtered pseudo-inverse of the observation transfer matrix of the last fit, mapping the
+    /// observed components to the amplitudes
+    pub vwu: Option<Array2<f64>>,
+    /// Cutoff `vwu` was computed with, it is kept while the cutoff and the observation locations
+    /// do not change
+    vwu_epsilon: f64,
+    /// Noise levels of the observations and the standard deviations they give, filled a chunk of
+    /// prediction locations at a time by `step_std`
+    std_cache: Option<(Vec<f64>, Vec<f64>)>,
 }
 
 impl SECS {
@@ -63,6 +191,12 @@ impl SECS {
             t_obs_flat_cache: None,
             pred_locs_cache: vec![],
             t_pred_cache: None,
+            t_pred_storage: TransferStorage::default(),
+            t_pred_build: None,
+            point_cache: PointTransferCache::default(),
+            vwu: None,
+            vwu_epsilon: 0.0,
+            std_cache: None,
         }
     }
 
@@ -90,61 +224,286 @@ impl SECS {
             );
             self.t_obs_flat_cache = Some(
                 t.clone()
-                    .into_shape((t.len() / self.sec_locs.len(), self.sec_locs.len()))
+                    .into_shape_with_order((t.len() / self.sec_locs.len(), self.sec_locs.len()))
                     .unwrap(),
             );
 
             self.obs_locs_cache = obs_locs;
+            self.vwu = None;
         }
 
-        // SVD
-        let vwu: Array2<f64> = svd(self.t_obs_flat_cache.as_ref().unwrap(), epsilon);
-        self.sec_amps = Some(obs_b.dot(&vwu.t()));
+        // SVD, only when the observation locations or the cutoff changed
+        if self.vwu.is_none() || self.vwu_epsilon != epsilon {
+            self.vwu = Some(svd(self.t_obs_flat_cache.as_ref().unwrap(), epsilon));
+            self.vwu_epsilon = epsilon;
+            self.std_cache = None;
+        }
+        self.sec_amps = Some(obs_b.dot(&self.vwu.as_ref().unwrap().t()));
     }
 
+    /// Compute the whole prediction transfer matrix at once, the canister spreads it over several
+    /// calls with `start_t_pred` and `step_t_pred` instead
+    #[allow(dead_code)]
     pub fn calc_t_pred(&mut self, pred_locs: &[GeographicalPoint], pred_altitude: f64) {
-        // check if transfer matrix was already computed for these locations
-        if pred_locs != self.pred_locs_cache {
-            self.t_pred_cache = Some(t_df(
-                pred_locs,
+        self.start_t_pred(pred_locs, pred_altitude);
+        self.step_t_pred(pred_locs.len());
+    }
+
+    /// Drop the prediction transfer matrix (and any computation in progress) so that the next
+    /// `calc_t_pred` recomputes it
+    pub fn clear_t_pred(&mut self) {
+        self.t_pred_cache = None;
+        self.pred_locs_cache = vec![];
+        self.t_pred_build = None;
+        self.std_cache = None;
+    }
+
+    pub fn t_pred_status(&self) -> TPredStatus {
+        match (&self.t_pred_build, &self.t_pred_cache) {
+            (Some(build), _) => TPredStatus::Building {
+                done: build.next as u64,
+                total: build.pred_locs.len() as u64,
+            },
+            (None, Some(_)) => TPredStatus::Ready {
+                total: self.pred_locs_cache.len() as u64,
+            },
+            (None, None) => TPredStatus::Missing,
+        }
+    }
+
+    /// Start computing the prediction transfer matrix incrementally, see `step_t_pred`.
+    ///
+    /// Nothing is done if the matrix was already computed for these locations, and a computation
+    /// in progress for the same locations and altitude is resumed rather than restarted, as long
+    /// as they are stored as `t_pred_storage`. Otherwise the current matrix is dropped, predictions
+    /// being unavailable until the new one is complete.
+    pub fn start_t_pred(
+        &mut self,
+        pred_locs: &[GeographicalPoint],
+        pred_altitude: f64,
+    ) -> TPredStatus {
+        let storage = self.t_pred_storage;
+        let in_progress = self.t_pred_build.as_ref().is_some_and(|build| {
+            build.pred_locs == pred_locs
+                && build.pred_altitude == pred_altitude
+                && build.t.storage() == storage
+        });
+        let computed = self
+            .t_pred_cache
+            .as_ref()
+            .is_some_and(|t| t.storage() == storage)
+            && pred_locs == self.pred_locs_cache;
+
+        if !in_progress && !computed {
+            // the matrix being replaced is dropped first so that heap memory never holds both
+            self.clear_t_pred();
+            self.t_pred_build = Some(TPredBuild {
+                pred_locs: pred_locs.to_vec(),
                 pred_altitude,
+                t: TransferMatrix::new(self.sec_locs.len(), storage),
+                next: 0,
+            });
+        }
+
+        self.t_pred_status()
+    }
+
+    /// Compute the transfer matrix rows of the next `max_rows` prediction locations of the
+    /// computation started by `start_t_pred`, the matrix replaces `t_pred_cache` once complete
+    pub fn step_t_pred(&mut self, max_rows: usize) -> TPredStatus {
+        if let Some(build) = self.t_pred_build.as_mut() {
+            let end = (build.next + max_rows).min(build.pred_locs.len());
+            let chunk = t_df(
+                &build.pred_locs[build.next..end],
+                build.pred_altitude,
                 &self.sec_locs,
                 self.sec_locs_altitude,
-            ));
-            self.pred_locs_cache = pred_locs.to_vec();
+            );
+            build.t.extend(&chunk);
+            build.next = end;
+
+            if build.next == build.pred_locs.len() {
+                let build = self.t_pred_build.take().unwrap();
+                self.t_pred_cache = Some(build.t);
+                self.pred_locs_cache = build.pred_locs;
+                self.std_cache = None;
+            }
         }
+
+        self.t_pred_status()
     }
 
     pub fn predict(&self) -> Vec<PredictionVector> {
-        let amps: &Array2<f64> = self.sec_amps.as_ref().unwrap();
-        let t_pred: &Array3<f64> = self.t_pred_cache.as_ref().unwrap();
+        self.predict_with(self.sec_amps.as_ref().unwrap())
+    }
+
+    /// Predict from the given amplitudes rather than those of the last fit
+    pub fn predict_with(&self, amps: &Array2<f64>) -> Vec<PredictionVector> {
+        let t_pred = self.t_pred_cache.as_ref().unwrap();
 
         assert_eq!(
             amps.shape()[1],
-            t_pred.shape()[2],
+            self.sec_locs.len(),
             "Dimension K mismatch for contraction"
         );
 
-        let temp = tensordot(amps, t_pred, &[Axis(1)], &[Axis(2)]);
-        let pred = temp.index_axis(Axis(0), 0);
-
         self.pred_locs_cache
             .iter()
-            .enumerate()
-            .map(|(i, loc)| PredictionVector {
+            .zip(t_pred.predict(amps.row(0)))
+            .map(|(loc, [i, j, k])| PredictionVector {
                 lon: loc.lon,
                 lat: loc.lat,
-                i: pred[[i, 0]],
-                j: pred[[i, 1]],
-                k: pred[[i, 2]],
+                i,
+                j,
+                k,
             })
             .collect()
     }
+
+    /// Posterior standard deviation in nT of the `i` component at every prediction location, as
+    /// computed by `step_std`. `None` until its computation for these priors is complete.
+    pub fn prediction_std(&self, sigmas: &[f64], field_sigma: f64) -> Option<Vec<f64>> {
+        let (key, std) = self.std_cache.as_ref()?;
+        let complete = *key == Self::std_key(sigmas, field_sigma)
+            && std.len() == self.t_pred_cache.as_ref()?.points();
+        complete.then(|| std.clone())
+    }
+
+    fn std_key(sigmas: &[f64], field_sigma: f64) -> Vec<f64> {
+        sigmas.iter().copied().chain([field_sigma]).collect()
+    }
+
+    /// Whether `step_std` is left to call for these priors: a fit and a prediction transfer
+    /// matrix are available, with `sigmas` ordered as `obs_locs_cache`, and the standard
+    /// deviations are not complete
+    pub fn std_pending(&self, sigmas: &[f64], field_sigma: f64) -> bool {
+        let estimable = self.t_pred_cache.is_some()
+            && self.t_obs_flat_cache.is_some()
+            && self
+                .vwu
+                .as_ref()
+                .is_some_and(|vwu| vwu.ncols() == 3 * sigmas.len());
+        estimable && self.prediction_std(sigmas, field_sigma).is_none()
+    }
+
+    /// Compute the standard deviations of the next `max_rows` prediction locations, see
+    /// `prediction_std`. Two independent terms are summed:
+    ///
+    /// * the noise of the observations (standard deviations `sigmas`, ordered as
+    ///   `obs_locs_cache`) propagated through the amplitudes, whose covariance is
+    ///   `VWU diag(σ²) VWUᵀ`, and the prediction transfer matrix `T`
+    /// * the part of the field the observations do not resolve, `‖T (I - VWU T_obs)‖ / ‖T‖`
+    ///   taken from a field of standard deviation `field_sigma`. Close to 0 under the stations,
+    ///   it reaches 1 where the prediction is pure extrapolation.
+    ///
+    /// Both take a couple of products with the observation transfer matrix per location, hence
+    /// the chunks. The result is kept until the observation locations, the priors or the
+    /// prediction locations change.
+    pub fn step_std(&mut self, sigmas: &[f64], field_sigma: f64, max_rows: usize) {
+        if !self.std_pending(sigmas, field_sigma) {
+            return;
+        }
+        let key = Self::std_key(sigmas, field_sigma);
+        if self
+            .std_cache
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != key)
+        {
+            self.std_cache = Some((key, vec![]));
+        }
+        let (Some(vwu), Some(t_obs), Some(t_pred), Some((_, std))) = (
+            &self.vwu,
+            &self.t_obs_flat_cache,
+            &self.t_pred_cache,
+            &mut self.std_cache,
+        ) else {
+            return;
+        };
+
+        let variances = sigmas.iter().flat_map(|s| [s * s; 3]).collect::<Vec<_>>();
+        let end = (std.len() + max_rows).min(t_pred.points());
+        // a row at a time, the unresolved part being as large as the transfer matrix
+        for point in std.len()..end {
+            let t = t_pred.row(point, 0);
+            // gain of each observed component on the predicted `i` component
+            let gain = t.dot(vwu);
+            let noise: f64 = gain.iter().zip(&variances).map(|(g, v)| g * g * v).sum();
+            let unresolved = &t - &gain.dot(t_obs);
+            let norm = t.dot(&t);
+            let resolution = if norm > 0.0 {
+                unresolved.dot(&unresolved) / norm
+            } else {
+                1.0
+            };
+            std.push((noise + field_sigma * field_sigma * resolution).sqrt());
+        }
+    }
+
+    /// Compute the standard deviations at every prediction location at once, the canister
+    /// spreads them over several calls with `step_std` instead
+    #[cfg(test)]
+    pub fn calc_std(&mut self, sigmas: &[f64], field_sigma: f64) -> Option<Vec<f64>> {
+        self.step_std(sigmas, field_sigma, usize::MAX);
+        self.prediction_std(sigmas, field_sigma)
+    }
+
+    /// Predict the field at arbitrary points from the amplitudes of the last fit, `None` until a
+    /// first fit. Their transfer rows are computed on the fly and kept in `point_cache`.
+    pub fn predict_points(
+        &mut self,
+        points: &[GeographicalPoint],
+        pred_altitude: f64,
+    ) -> Option<Vec<PredictionVector>> {
+        let amps = self.sec_amps.as_ref()?.row(0).to_owned();
+
+        let mut rows: Vec<Option<Array2<f64>>> = points
+            .iter()
+            .map(|p| self.point_cache.get(p, pred_altitude))
+            .collect();
+        let missing: Vec<GeographicalPoint> = points
+            .iter()
+            .zip(&rows)
+            .filter(|(_, row)| row.is_none())
+            .map(|(p, _)| *p)
+            .collect();
+        if !missing.is_empty() {
+            let t = t_df(
+                &missing,
+                pred_altitude,
+                &self.sec_locs,
+                self.sec_locs_altitude,
+            );
+            let mut computed = missing.iter().zip(t.outer_iter());
+            for row in rows.iter_mut().filter(|row| row.is_none()) {
+                let (point, t) = computed.next().unwrap();
+                self.point_cache.insert(point, pred_altitude, t.to_owned());
+                *row = Some(t.to_owned());
+            }
+        }
+
+        Some(
+            points
+                .iter()
+                .zip(rows)
+                .map(|(point, row)| {
+                    let b = row.unwrap().dot(&amps);
+                    PredictionVector {
+                        lon: point.lon,
+                        lat: point.lat,
+                        i: b[0],
+                        j: b[1],
+                        k: b[2],
+                    }
+                })
+                .collect(),
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::geo::geographical_grid;
     use approx::assert_relative_eq;
     use std::fs;
 
@@ -333,4 +692,259 @@ mod tests {
             assert_relative_eq!(actual.k, expected.k, max_relative = 1e-10);
         }
     }
+
+    #[test]
+    fn test_predict_points_matches_grid() {
+        let sec_locs = geographical_grid(50.0..70.0, 4, 0.0..30.0, 5);
+        let pred_locs = geographical_grid(45.0..75.0, 3, -10.0..40.0, 4);
+        let mut secs = SECS::new(sec_locs, 110e3);
+        assert!(secs.predict_points(&pred_locs, 0.0).is_none());
+
+        secs.fit(
+            &[
+                ObservationVector {
+                    lon: 10.0,
+                    lat: 60.0,
+                    i: -300.0,
+                    j: 20.0,
+                    k: 0.0,
+                },
+                ObservationVector {
+                    lon: 20.0,
+                    lat: 65.0,
+                    i: -150.0,
+                    j: -10.0,
+                    k: 0.0,
+                },
+            ],
+            0.0,
+            0.1,
+        );
+        secs.calc_t_pred(&pred_locs, 0.0);
+        let expected = secs.predict();
+
+        // twice, the second time from the cache
+        for _ in 0..2 {
+            let points = secs.predict_points(&pred_locs, 0.0).unwrap();
+            for (actual, expected) in points.iter().zip(&expected) {
+                assert_eq!(actual.lat, expected.lat);
+                assert_relative_eq!(actual.i, expected.i, max_relative = 1e-10);
+                assert_relative_eq!(actual.j, expected.j, max_relative = 1e-10);
+                assert_relative_eq!(actual.k, expected.k, max_relative = 1e-10);
+            }
+        }
+        assert_eq!(secs.point_cache.rows.len(), pred_locs.len());
+    }
+
+    #[test]
+    fn test_prediction_std() {
+        let sec_locs = geographical_grid(50.0..70.0, 5, 0.0..30.0, 7);
+        let station = |lat: f64, lon: f64| ObservationVector {
+            lon,
+            lat,
+            i: -100.0,
+            j: 0.0,
+            k: 0.0,
+        };
+        // under the stations, between them and far from them
+        let pred_locs = vec![
+            GeographicalPoint::new(60.0, 10.0),
+            GeographicalPoint::new(62.5, 15.0),
+            GeographicalPoint::new(52.0, 28.0),
+        ];
+        let mut secs = SECS::new(sec_locs, 110e3);
+        assert!(secs.calc_std(&[10.0; 3], 100.0).is_none());
+
+        let stations = [station(60.0, 10.0), station(65.0, 20.0), station(58.0, 5.0)];
+        secs.fit(&stations, 0.0, 0.1);
+        secs.calc_t_pred(&pred_locs, 0.0);
+        assert!(secs.std_pending(&[10.0; 3], 100.0));
+        // computed a location at a time
+        secs.step_std(&[10.0; 3], 100.0, 2);
+        assert!(secs.prediction_std(&[10.0; 3], 100.0).is_none());
+        secs.step_std(&[10.0; 3], 100.0, 2);
+        let std = secs.prediction_std(&[10.0; 3], 100.0).unwrap();
+        assert!(!secs.std_pending(&[10.0; 3], 100.0));
+        assert!(std[0] < std[1] && std[1] < std[2], "{std:?}");
+
+        // noisier stations, less resolved field
+        let noisy = secs.calc_std(&[50.0, 10.0, 10.0], 100.0).unwrap();
+        assert!(noisy[0] > std[0]);
+        let unresolved = secs.calc_std(&[10.0; 3], 200.0).unwrap();
+        assert!(unresolved[2] > std[2]);
+        assert!(secs.calc_std(&[10.0; 2], 100.0).is_none());
+        assert!(!secs.std_pending(&[10.0; 2], 100.0));
+    }
+
+    #[test]
+    fn test_prediction_std_survives_refit() {
+        let sec_locs = geographical_grid(50.0..70.0, 5, 0.0..30.0, 7);
+        let station = |lat: f64, lon: f64, i: f64| ObservationVector {
+            lon,
+            lat,
+            i,
+            j: 0.0,
+            k: 0.0,
+        };
+        let mut secs = SECS::new(sec_locs, 110e3);
+        secs.fit(
+            &[station(60.0, 10.0, -100.0), station(65.0, 20.0, 0.0)],
+            0.0,
+            0.1,
+        );
+        secs.calc_t_pred(&[GeographicalPoint::new(62.0, 15.0)], 0.0);
+        let std = secs.calc_std(&[10.0; 2], 100.0).unwrap();
+
+        // the same stations observing another field
+        secs.fit(
+            &[station(60.0, 10.0, 50.0), station(65.0, 20.0, -300.0)],
+            0.0,
+            0.1,
+        );
+        assert_eq!(secs.prediction_std(&[10.0; 2], 100.0), Some(std));
+        assert!(!secs.std_pending(&[10.0; 2], 100.0));
+
+        // another cutoff or other stations have to be estimated again
+        secs.fit(
+            &[station(60.0, 10.0, 50.0), station(65.0, 20.0, -300.0)],
+            0.0,
+            0.05,
+        );
+        assert!(secs.std_pending(&[10.0; 2], 100.0));
+        secs.calc_std(&[10.0; 2], 100.0).unwrap();
+        secs.fit(
+            &[station(60.0, 10.0, 50.0), station(66.0, 20.0, -300.0)],
+            0.0,
+            0.05,
+        );
+        assert!(secs.prediction_std(&[10.0; 2], 100.0).is_none());
+    }
+
+    #[test]
+    fn test_validate_observations() {
+        let station = ObservationVector {
+            lon: 10.0,
+            lat: 60.0,
+            i: -100.0,
+            j: 0.0,
+            k: 0.0,
+        };
+        assert_eq!(validate_observations(&[station]), Ok(()));
+        assert!(validate_observations(&[
+            station,
+            ObservationVector {
+                i: f64::NAN,
+                ..station
+            }
+        ])
+        .is_err());
+        assert!(validate_observations(&[ObservationVector {
+            lon: f64::INFINITY,
+            ..station
+        }])
+        .is_err());
+        assert!(validate_observations(&[ObservationVector {
+            lat: 95.0,
+            ..station
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn test_point_cache_eviction() {
+        let mut secs = SECS::new(vec![GeographicalPoint::new(60.0, 10.0)], 110e3);
+        secs.sec_amps = Some(Array2::ones((1, 1)));
+        let points = geographical_grid(50.0..70.0, 10, 0.0..30.0, 10);
+
+        secs.predict_points(&points, 0.0);
+        assert_eq!(secs.point_cache.rows.len(), POINT_CACHE_SIZE);
+        // the first points were evicted, the last ones kept
+        assert!(secs.point_cache.get(&points[0], 0.0).is_none());
+        assert!(secs.point_cache.get(&points[99], 0.0).is_some());
+        // another altitude does not use the cached rows
+        assert!(secs.point_cache.get(&points[99], 110e3).is_none());
+
+        secs.predict_points(&points[..1], 110e3);
+        assert_eq!(secs.point_cache.rows.len(), 1);
+    }
+
+    #[test]
+    fn test_chunked_t_pred_matches_t_df() {
+        let sec_locs = geographical_grid(50.0..70.0, 4, 0.0..30.0, 5);
+        let pred_locs = geographical_grid(45.0..75.0, 5, -10.0..40.0, 7);
+        let mut secs = SECS::new(sec_locs.clone(), 110e3);
+
+        assert_eq!(secs.t_pred_status(), TPredStatus::Missing);
+        assert_eq!(
+            secs.start_t_pred(&pred_locs, 0.0),
+            TPredStatus::Building { done: 0, total: 35 }
+        );
+        assert_eq!(
+            secs.step_t_pred(16),
+            TPredStatus::Building {
+                done: 16,
+                total: 35
+            }
+        );
+        // resuming the same computation keeps the progress
+        assert_eq!(
+            secs.start_t_pred(&pred_locs, 0.0),
+            TPredStatus::Building {
+                done: 16,
+                total: 35
+            }
+        );
+        secs.step_t_pred(16);
+        assert_eq!(secs.step_t_pred(16), TPredStatus::Ready { total: 35 });
+        assert_eq!(
+            secs.start_t_pred(&pred_locs, 0.0),
+            TPredStatus::Ready { total: 35 }
+        );
+
+        let expected = t_df(&pred_locs, 0.0, &sec_locs, 110e3);
+        let t_pred = secs.t_pred_cache.as_ref().unwrap();
+        assert_eq!(t_pred.points(), pred_locs.len());
+        for (point, rows) in expected.outer_iter().enumerate() {
+            for (component, row) in rows.outer_iter().enumerate() {
+                assert_eq!(t_pred.row(point, component), row);
+            }
+        }
+        assert_eq!(secs.pred_locs_cache, pred_locs);
+
+        // another storage restarts the computation
+        secs.t_pred_storage = TransferStorage {
+            single_precision: true,
+            prune_below: 0.0,
+        };
+        assert_eq!(
+            secs.start_t_pred(&pred_locs, 0.0),
+            TPredStatus::Building { done: 0, total: 35 }
+        );
+        // the previous matrix is dropped rather than kept next to its replacement
+        assert!(secs.t_pred_cache.is_none());
+
+        secs.clear_t_pred();
+        assert_eq!(secs.t_pred_status(), TPredStatus::Missing);
+    }
+
+    #[test]
+    fn test_t_pred_status_merge() {
+        let ready = TPredStatus::Ready { total: 10 };
+        let building = TPredStatus::Building { done: 2, total: 5 };
+
+        assert_eq!(
+            TPredStatus::Missing.merge(TPredStatus::Missing),
+            TPredStatus::Missing
+        );
+        assert_eq!(ready.merge(ready), TPredStatus::Ready { total: 20 });
+        assert_eq!(
+            ready.merge(building),
+            TPredStatus::Building {
+                done: 12,
+                total: 15
+            }
+        );
+        assert_eq!(TPredStatus::Missing.merge(ready), ready);
+        assert_eq!(building.merge(TPredStatus::Missing), building);
+    }
 }
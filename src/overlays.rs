@@ -1,7 +1,14 @@
 use serde::{Deserialize, Serialize};
 use std::f64::consts::PI;
 
-use crate::{geo::R_EARTH, model::PredictionVector};
+use crate::{
+    coverage::Coverage,
+    geo::{GeographicalPoint, GridDefinition, Hemisphere, R_EARTH},
+    model::PredictionVector,
+    scoring::{LineOfSight, ScoringProfile},
+    solar::{magnetic_local_time, solar_zenith_angle},
+    sphere::angular_distance_and_bearing,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct ScoreVector {
@@ -10,53 +17,28 @@ pub struct ScoreVector {
     pub score: f64,
 }
 
-/// Given the value of the i component of a predicted vector, ponderate to give
-/// a score between 0 and 10
-/// Generated from a curve with the following points.
-/// 0 0
-/// 50 2
-/// 100 3
-/// 200 4
-/// 800 10
-fn ponderate_i(i: f64) -> f64 {
-    let numerator = -0.05732817 - 22.81964;
-    let denominator = 1.0 + (i / 1055.17).powf(0.8849212);
-    let result = 22.81964 + numerator / denominator;
-
-    result.clamp(0.0, 10.0)
-}
-
-/// Ponderate the derivative of the `i` component of the vector
-pub fn ponderate_didt(didt: f64) -> f64 {
-    if didt < 10f64 {
-        0f64
-    } else {
-        didt / 10f64
-    }
-}
-
 pub trait IntoScores {
-    fn into_scores(self) -> Vec<ScoreVector>;
-    fn into_derivative_scores(self) -> Vec<ScoreVector>;
+    fn into_scores(self, profile: &ScoringProfile) -> Vec<ScoreVector>;
+    fn into_derivative_scores(self, profile: &ScoringProfile) -> Vec<ScoreVector>;
 }
 
 impl IntoScores for Vec<PredictionVector> {
-    fn into_scores(self) -> Vec<ScoreVector> {
+    fn into_scores(self, profile: &ScoringProfile) -> Vec<ScoreVector> {
         self.into_iter()
             .map(|pv| ScoreVector {
                 lat: pv.lat,
                 lon: pv.lon,
-                score: ponderate_i(pv.i.abs()),
+                score: profile.intensity_score(pv.i),
             })
             .collect()
     }
 
-    fn into_derivative_scores(self) -> Vec<ScoreVector> {
+    fn into_derivative_scores(self, profile: &ScoringProfile) -> Vec<ScoreVector> {
         self.into_iter()
             .map(|pv| ScoreVector {
                 lat: pv.lat,
                 lon: pv.lon,
-                score: ponderate_didt(pv.i.abs()),
+                score: profile.derivative_score(pv.i),
             })
             .collect()
     }
@@ -77,87 +59,567 @@ fn approx_distance(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
     R_EARTH * f64::sqrt(x * x + y * y)
 }
 
-/// Given a distance in km (usually distance from geomagnetic pole) return a weight
-/// from 0 to 1 computed according to the follow points
-/// 0 0.3
-/// 2200 1
-/// 2700 1
-/// 3300 0.5
-/// 4000 0.2
-fn auroral_zone_weight(d: f64) -> f64 {
-    if (0.0..=2200.0).contains(&d) {
-        -2.0790e-11 * d.powi(3) - 7.6000e-67 * d.powi(2) + 5.5517e-4 * d + 0.0
-    } else if (2200.0..=2700.0).contains(&d) {
-        -7.3879e-10 * d.powi(3) + 4.7388e-6 * d.powi(2) - 9.8701e-3 * d + 7.6452
-    } else if (2700.0..=3300.0).contains(&d) {
-        9.7750e-10 * d.powi(3) - 9.1631e-6 * d.powi(2) + 2.7665e-2 * d - 26.136
-    } else if (3300.0..=5000.0).contains(&d) {
-        -1.0080e-10 * d.powi(3) + 1.5121e-6 * d.powi(2) - 7.5632e-3 * d + 12.615
-    } else {
-        0.3
+/// Activity the auroral zone of a prediction is displaced with, see `OvalModel`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OvalActivity {
+    /// Peak westward electrojet in nT, the opposite of the most negative `i` component of the
+    /// absolute prediction
+    pub westward_electrojet: f64,
+    /// Time of the prediction in nanoseconds since the epoch
+    pub timestamp: u64,
+}
+
+impl OvalActivity {
+    pub fn from_prediction(raw: &[PredictionVector], timestamp: u64) -> Self {
+        OvalActivity {
+            westward_electrojet: raw.iter().map(|pv| -pv.i).fold(0.0, f64::max),
+            timestamp,
+        }
     }
 }
 
-/// Given a ScoreVector ponderate the score depending on its vicinity to the auroral oval
-pub fn ponderate_auroral_zone(lon: f64, lat: f64, score: f64) -> f64 {
-    let geomag_n_pole: (f64, f64) = (-72.6_f64, 80.9_f64);
+/// Given a ScoreVector ponderate the score depending on its vicinity to the auroral oval, the
+/// distance is measured from the geomagnetic pole of the hemisphere of the vector
+pub fn ponderate_auroral_zone(
+    lon: f64,
+    lat: f64,
+    score: f64,
+    profile: &ScoringProfile,
+    activity: &OvalActivity,
+) -> f64 {
+    let pole = Hemisphere::of(lat).geomagnetic_pole();
 
-    let d = approx_distance(lat, lon, geomag_n_pole.1, geomag_n_pole.0);
-    let w = auroral_zone_weight(d);
+    // approx_distance is in meters while the weight expects kilometers
+    let d = approx_distance(lat, lon, pole.lat, pole.lon) / 1e3;
+    let mlt = magnetic_local_time(&GeographicalPoint::new(lat, lon), activity.timestamp);
+    let w = profile.auroral_zone_weight(d, mlt, activity.westward_electrojet);
 
-    // score: f64::max(vec.score * w, ponderate_didt())
+    // score: f64::max(vec.score * w, profile.derivative_score())
     score * w
 }
 
-/// Encodes the score and the derivative flag into a single byte, see specification document
+/// Given a ScoreVector ponderate the score depending on the darkness of the sky at its point at
+/// the time of the prediction, in nanoseconds since the epoch
+pub fn ponderate_darkness(
+    lon: f64,
+    lat: f64,
+    score: f64,
+    profile: &ScoringProfile,
+    timestamp: u64,
+) -> f64 {
+    if profile.darkness.is_none() {
+        return score;
+    }
+
+    let zenith = solar_zenith_angle(&GeographicalPoint::new(lat, lon), timestamp);
+    score * profile.darkness_weight(zenith)
+}
+
+/// Largest angular distance in degrees from which an emission at `altitude` meters stands at
+/// least `min_elevation` degrees above the horizon
+pub fn sight_distance(altitude: f64, min_elevation: f64) -> f64 {
+    let elevation = min_elevation.to_radians();
+    ((R_EARTH * elevation.cos() / (R_EARTH + altitude)).acos() - elevation).to_degrees()
+}
+
+/// Best score seen from each point of the grid, among the points of the grid whose emissions
+/// stand high enough above its horizon. Scores not laid on the grid are returned as they are.
+pub fn line_of_sight(
+    grid: &GridDefinition,
+    scores: &[ScoreVector],
+    sight: &LineOfSight,
+) -> Vec<ScoreVector> {
+    let lon_steps = grid.lon_steps as usize;
+    if scores.len() != grid.size() || lon_steps == 0 {
+        return scores.to_vec();
+    }
+    let max_distance = sight_distance(sight.emission_altitude, sight.min_elevation);
+    let rows: Vec<&[ScoreVector]> = scores.chunks(lon_steps).collect();
+    let points = |row: &[ScoreVector]| -> Vec<GeographicalPoint> {
+        row.iter()
+            .map(|v| GeographicalPoint::new(v.lat, v.lon))
+            .collect()
+    };
+
+    let mut seen = Vec::with_capacity(scores.len());
+    for row in &rows {
+        // only the rows within reach in latitude may be seen
+        let sources: Vec<ScoreVector> = rows
+            .iter()
+            .filter(|r| (r[0].lat - row[0].lat).abs() <= max_distance)
+            .flat_map(|r| r.iter().copied())
+            .collect();
+        let (theta, _) = angular_distance_and_bearing(&points(row), &points(&sources));
+
+        for (ground, distances) in row.iter().zip(theta.rows()) {
+            let score = sources
+                .iter()
+                .zip(distances.iter())
+                .filter(|(_, d)| d.to_degrees() <= max_distance)
+                .map(|(source, _)| source.score)
+                .fold(ground.score, f64::max);
+            seen.push(ScoreVector { score, ..*ground });
+        }
+    }
+    seen
+}
+
+/// Encoded scores are kept with a precision of `1 / SCORE_SCALE`
+pub const SCORE_SCALE: u16 = 1000;
+
+/// Derivative bit set when the derivative map drove the score, its score being above the one of
+/// the absolute map
+pub const DERIVATIVE_DRIVEN: u8 = 0b01;
+/// Derivative bit set when dB/dt is rising (the derivative of the `i` component is positive),
+/// falling otherwise
+pub const DERIVATIVE_RISING: u8 = 0b10;
+
+/// Encodes the score and the derivative flag into a single byte, see specification document.
+///
+/// The score in 0..10 is kept with a precision of `1 / SCORE_SCALE` in the upper 14 bits, the 2 lower bits
+/// are the derivative bits (`DERIVATIVE_DRIVEN` and `DERIVATIVE_RISING`).
 pub fn encode_score(score: f64, derivative: u8) -> u16 {
     let clamped = score.clamp(0.0, 10.0);
-    let scaled = (clamped * 1000.0).round() as u16;
+    let scaled = (clamped * SCORE_SCALE as f64).round() as u16;
 
     let u2 = derivative & 0b11;
 
     (scaled << 2) | (u2 as u16)
 }
 
+/// Score decoded from its `encode_score` representation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodedScore {
+    pub score: f64,
+    /// Whether the derivative map drove the score
+    pub derivative_driven: bool,
+    /// Whether dB/dt was rising, only meaningful when a derivative was predicted
+    pub rising: bool,
+}
+
+/// Inverse of `encode_score`
+pub fn decode_score(encoded: u16) -> DecodedScore {
+    let bits = (encoded & 0b11) as u8;
+
+    DecodedScore {
+        score: (encoded >> 2) as f64 / SCORE_SCALE as f64,
+        derivative_driven: bits & DERIVATIVE_DRIVEN != 0,
+        rising: bits & DERIVATIVE_RISING != 0,
+    }
+}
+
+/// Derivative bits of a combined score, see `encode_score`
+fn derivative_bits(abs_score: f64, drv_score: f64, didt: f64) -> u8 {
+    let mut bits = 0;
+    if drv_score > abs_score {
+        bits |= DERIVATIVE_DRIVEN;
+    }
+    if didt > 0.0 {
+        bits |= DERIVATIVE_RISING;
+    }
+    bits
+}
+
 pub trait Overlays {
-    fn ponderate_auroral_zone(self) -> Self;
+    fn ponderate_auroral_zone(self, profile: &ScoringProfile, activity: &OvalActivity) -> Self;
+    fn ponderate_darkness(self, profile: &ScoringProfile, timestamp: u64) -> Self;
+    fn ponderate_uncertainty(self, profile: &ScoringProfile, std: Option<&[f64]>) -> Self;
+    fn ponderate_coverage(self, profile: &ScoringProfile, coverage: &[Coverage]) -> Self;
+    fn line_of_sight(self, profile: &ScoringProfile, grid: &GridDefinition) -> Self;
     fn encode(self) -> Vec<u16>;
-    fn max_score_vectors(self, vec: Vec<ScoreVector>) -> Self;
+    fn encode_with_derivative(self, drv: &[ScoreVector], drv_raw: &[PredictionVector]) -> Vec<u16>;
 }
 
 impl Overlays for Vec<ScoreVector> {
-    fn ponderate_auroral_zone(self) -> Self {
+    fn ponderate_auroral_zone(self, profile: &ScoringProfile, activity: &OvalActivity) -> Self {
         self.into_iter()
             .map(|v| ScoreVector {
                 lat: v.lat,
                 lon: v.lon,
-                score: ponderate_auroral_zone(v.lon, v.lat, v.score),
+                score: ponderate_auroral_zone(v.lon, v.lat, v.score, profile, activity),
             })
             .collect()
     }
 
-    fn encode(self) -> Vec<u16> {
+    fn ponderate_darkness(self, profile: &ScoringProfile, timestamp: u64) -> Self {
         self.into_iter()
-            .map(|v| {
-                encode_score(
-                    v.score,
-                    // derivative not implemented yet, 0
-                    // TODO: include derivative
-                    0,
-                )
+            .map(|v| ScoreVector {
+                lat: v.lat,
+                lon: v.lon,
+                score: ponderate_darkness(v.lon, v.lat, v.score, profile, timestamp),
+            })
+            .collect()
+    }
+
+    /// Fade the scores with the standard deviation of their prediction in nT, ordered as the
+    /// scores. Scores are kept as they are when it is not known.
+    fn ponderate_uncertainty(self, profile: &ScoringProfile, std: Option<&[f64]>) -> Self {
+        match std {
+            Some(std) if std.len() == self.len() => self
+                .into_iter()
+                .zip(std)
+                .map(|(v, std)| ScoreVector {
+                    score: v.score * profile.uncertainty_weight(*std),
+                    ..v
+                })
+                .collect(),
+            _ => self,
+        }
+    }
+
+    /// Weight the scores with the coverage of their point by the stations, ordered as the scores.
+    /// Scores are kept as they are when it is not known.
+    fn ponderate_coverage(self, profile: &ScoringProfile, coverage: &[Coverage]) -> Self {
+        if coverage.len() != self.len() {
+            return self;
+        }
+
+        self.into_iter()
+            .zip(coverage)
+            .map(|(v, coverage)| ScoreVector {
+                score: v.score * profile.coverage_weight(coverage),
+                ..v
             })
             .collect()
     }
 
-    /// Select max score between two identical score vectors
-    fn max_score_vectors(self, vec: Vec<ScoreVector>) -> Self {
+    /// Scores seen from each point of the grid when the profile asks for it, see `line_of_sight`
+    fn line_of_sight(self, profile: &ScoringProfile, grid: &GridDefinition) -> Self {
+        match &profile.line_of_sight {
+            Some(sight) => line_of_sight(grid, &self, sight),
+            None => self,
+        }
+    }
+
+    /// Encode scores predicted without derivative, the derivative bits are left unset
+    fn encode(self) -> Vec<u16> {
+        self.into_iter().map(|v| encode_score(v.score, 0)).collect()
+    }
+
+    /// Encode the max of these absolute scores and the derivative scores `drv` (predicted from the
+    /// raw vectors `drv_raw`), recording in the derivative bits which one drove each score and
+    /// the direction of dB/dt
+    fn encode_with_derivative(self, drv: &[ScoreVector], drv_raw: &[PredictionVector]) -> Vec<u16> {
         self.iter()
-            .zip(vec.iter())
-            .map(|(v1, v2)| ScoreVector {
-                lat: v1.lat,
-                lon: v1.lon,
-                score: v1.score.max(v2.score),
+            .zip(drv.iter().zip(drv_raw.iter()))
+            .map(|(abs, (drv, raw))| {
+                encode_score(
+                    abs.score.max(drv.score),
+                    derivative_bits(abs.score, drv.score, raw.i),
+                )
             })
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn test_auroral_zone_both_hemispheres() {
+        // Tromsø and its conjugate point around the southern geomagnetic pole
+        let profile = ScoringProfile {
+            oval: None,
+            ..ScoringProfile::default()
+        };
+        let quiet = OvalActivity::from_prediction(&[], 0);
+        let north = ponderate_auroral_zone(18.9, 69.6, 10.0, &profile, &quiet);
+        let south = ponderate_auroral_zone(18.9 + 180.0, -69.5, 10.0, &profile, &quiet);
+        assert!(north > 5.0);
+        assert_relative_eq!(north, south, epsilon = 0.5);
+
+        // Far from both ovals nothing is kept
+        assert_relative_eq!(
+            ponderate_auroral_zone(0.0, -10.0, 10.0, &profile, &quiet),
+            0.0
+        );
+    }
+
+    #[test]
+    fn test_auroral_zone_northern_weights() {
+        // with the distance in kilometers, before which every point got the same weight
+        let profile = ScoringProfile {
+            oval: None,
+            ..ScoringProfile::default()
+        };
+        let quiet = OvalActivity::from_prediction(&[], 0);
+        let weight = |lat: f64, lon: f64| ponderate_auroral_zone(lon, lat, 1.0, &profile, &quiet);
+
+        let pole = weight(80.9, -72.6);
+        let tromso = weight(69.6, 18.9);
+        let fairbanks = weight(64.8, -147.7);
+        let oslo = weight(59.9, 10.7);
+        let rome = weight(41.9, 12.5);
+        // under the oval, then decreasing equatorward of it, with some aurora inside it
+        assert!(tromso > 0.8 && fairbanks > 0.6, "{tromso} {fairbanks}");
+        assert!(tromso > oslo && oslo > rome, "{oslo} {rome}");
+        assert!(pole > 0.0 && pole < tromso, "{pole}");
+        assert_relative_eq!(rome, 0.0);
+    }
+
+    #[test]
+    fn test_sight_distance() {
+        assert_relative_eq!(
+            sight_distance(110e3, 0.0),
+            crate::viewline::horizon_distance(110e3),
+            epsilon = 1e-9
+        );
+        // around 500 km at 10° for emissions at 110 km
+        assert_relative_eq!(sight_distance(110e3, 10.0), 4.5, epsilon = 0.1);
+        assert!(sight_distance(200e3, 10.0) > sight_distance(110e3, 10.0));
+        assert_relative_eq!(sight_distance(110e3, 90.0), 0.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn test_line_of_sight() {
+        let grid = GridDefinition {
+            lat_start: 50.0,
+            lat_end: 70.0,
+            lat_steps: 11,
+            lon_start: 0.0,
+            lon_end: 20.0,
+            lon_steps: 11,
+        };
+        // a single bright spot at 66°N 10°E
+        let scores: Vec<ScoreVector> = grid
+            .points()
+            .iter()
+            .map(|p| ScoreVector {
+                lat: p.lat,
+                lon: p.lon,
+                score: if p.lat == 66.0 && p.lon == 10.0 {
+                    8.0
+                } else {
+                    1.0
+                },
+            })
+            .collect();
+        let at = |scores: &[ScoreVector], lat: f64, lon: f64| {
+            scores
+                .iter()
+                .find(|v| v.lat == lat && v.lon == lon)
+                .unwrap()
+                .score
+        };
+
+        let seen = line_of_sight(&grid, &scores, &LineOfSight::default());
+        assert_relative_eq!(at(&seen, 66.0, 10.0), 8.0);
+        // 4° south is within the 4.5° of sight, 6° south is not
+        assert_relative_eq!(at(&seen, 62.0, 10.0), 8.0);
+        assert_relative_eq!(at(&seen, 60.0, 10.0), 1.0);
+        // 10° of longitude at 66°N are around 4°
+        assert_relative_eq!(at(&seen, 66.0, 20.0), 8.0);
+        assert_relative_eq!(at(&seen, 56.0, 0.0), 1.0);
+
+        // lower emissions are seen from closer
+        let low = LineOfSight {
+            emission_altitude: 50e3,
+            min_elevation: 10.0,
+        };
+        assert_relative_eq!(at(&line_of_sight(&grid, &scores, &low), 62.0, 10.0), 1.0);
+
+        let unchanged = scores
+            .clone()
+            .line_of_sight(&ScoringProfile::default(), &grid);
+        assert_relative_eq!(at(&unchanged, 62.0, 10.0), 1.0);
+    }
+
+    #[test]
+    fn test_uncertainty_overlay() {
+        use crate::scoring::UncertaintyFade;
+
+        let profile = ScoringProfile {
+            uncertainty: Some(UncertaintyFade {
+                half_weight_std: 20.0,
+            }),
+            ..ScoringProfile::default()
+        };
+        let scores = vec![
+            ScoreVector {
+                lat: 60.0,
+                lon: 10.0,
+                score: 6.0,
+            };
+            2
+        ];
+
+        let faded = scores
+            .clone()
+            .ponderate_uncertainty(&profile, Some(&[0.0, 60.0]));
+        assert_relative_eq!(faded[0].score, 6.0);
+        assert_relative_eq!(faded[1].score, 1.5);
+
+        let unknown = scores.ponderate_uncertainty(&profile, Some(&[0.0]));
+        assert_relative_eq!(unknown[1].score, 6.0);
+    }
+
+    #[test]
+    fn test_coverage_overlay() {
+        use crate::scoring::CoveragePolicy;
+
+        let profile = ScoringProfile {
+            coverage: Some(CoveragePolicy {
+                mask: true,
+                ..CoveragePolicy::default()
+            }),
+            ..ScoringProfile::default()
+        };
+        let scores = vec![
+            ScoreVector {
+                lat: 60.0,
+                lon: 10.0,
+                score: 6.0,
+            };
+            2
+        ];
+        let coverage = [
+            Coverage {
+                distance: 50.0,
+                stations: 4,
+            },
+            Coverage {
+                distance: 3000.0,
+                stations: 0,
+            },
+        ];
+
+        let masked = scores.clone().ponderate_coverage(&profile, &coverage);
+        assert_relative_eq!(masked[0].score, 6.0);
+        assert_relative_eq!(masked[1].score, 0.0);
+
+        let unknown = scores.ponderate_coverage(&profile, &[]);
+        assert_relative_eq!(unknown[1].score, 6.0);
+    }
+
+    #[test]
+    fn test_darkness_overlay() {
+        use crate::{scoring::Darkness, solar::Twilight};
+
+        // 2024-12-21 at 12 UT, daylight in Europe and night over the Pacific
+        let timestamp = 20_078 * 86_400 * 1_000_000_000 + 12 * 3_600 * 1_000_000_000;
+        let scores = vec![
+            ScoreVector {
+                lat: 45.0,
+                lon: 10.0,
+                score: 6.0,
+            },
+            ScoreVector {
+                lat: 45.0,
+                lon: -170.0,
+                score: 6.0,
+            },
+        ];
+
+        let unchanged = scores
+            .clone()
+            .ponderate_darkness(&ScoringProfile::default(), timestamp);
+        assert_relative_eq!(unchanged[0].score, 6.0);
+
+        let profile = ScoringProfile {
+            darkness: Some(Darkness {
+                twilight: Twilight::Astronomical,
+                mask: true,
+            }),
+            ..ScoringProfile::default()
+        };
+        let faded = scores.ponderate_darkness(&profile, timestamp);
+        assert_relative_eq!(faded[0].score, 0.0);
+        assert_relative_eq!(faded[1].score, 6.0);
+    }
+
+    #[test]
+    fn test_auroral_zone_activity() {
+        let profile = ScoringProfile {
+            oval: Some(crate::scoring::OvalModel::default()),
+            ..ScoringProfile::default()
+        };
+        let raw = |i: f64| PredictionVector {
+            lon: 10.0,
+            lat: 65.0,
+            i,
+            j: 0.0,
+            k: 0.0,
+        };
+        // Germany around magnetic midnight
+        let timestamp = 21 * 3_600 * 1_000_000_000;
+        let quiet = OvalActivity::from_prediction(&[raw(50.0), raw(-150.0)], timestamp);
+        assert_relative_eq!(quiet.westward_electrojet, 150.0);
+        let storm = OvalActivity::from_prediction(&[raw(-1500.0)], timestamp);
+
+        let quiet = ponderate_auroral_zone(10.0, 52.0, 10.0, &profile, &quiet);
+        let storm = ponderate_auroral_zone(10.0, 52.0, 10.0, &profile, &storm);
+        assert!(storm > 2.0 * quiet, "{quiet} {storm}");
+    }
+
+    fn score(score: f64) -> ScoreVector {
+        ScoreVector {
+            lat: 65.0,
+            lon: 10.0,
+            score,
+        }
+    }
+
+    fn raw(i: f64) -> PredictionVector {
+        PredictionVector {
+            lon: 10.0,
+            lat: 65.0,
+            i,
+            j: 0.0,
+            k: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_round_trip() {
+        for s in [0.0, 0.001, 3.5, 7.25, 10.0] {
+            for bits in 0..4 {
+                let decoded = decode_score(encode_score(s, bits));
+                assert_relative_eq!(decoded.score, s);
+                assert_eq!(decoded.derivative_driven, bits & DERIVATIVE_DRIVEN != 0);
+                assert_eq!(decoded.rising, bits & DERIVATIVE_RISING != 0);
+            }
+        }
+
+        // out of range scores are clamped
+        assert_relative_eq!(decode_score(encode_score(12.0, 0)).score, 10.0);
+        assert_relative_eq!(decode_score(encode_score(-1.0, 0)).score, 0.0);
+    }
+
+    #[test]
+    fn test_encode_with_derivative() {
+        let abs = vec![score(4.0), score(2.0), score(5.0)];
+        let drv = [score(1.0), score(6.0), score(5.0)];
+        let drv_raw = [raw(20.0), raw(-60.0), raw(50.0)];
+
+        let decoded: Vec<DecodedScore> = abs
+            .encode_with_derivative(&drv, &drv_raw)
+            .into_iter()
+            .map(decode_score)
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                DecodedScore {
+                    score: 4.0,
+                    derivative_driven: false,
+                    rising: true,
+                },
+                DecodedScore {
+                    score: 6.0,
+                    derivative_driven: true,
+                    rising: false,
+                },
+                DecodedScore {
+                    score: 5.0,
+                    derivative_driven: false,
+                    rising: true,
+                },
+            ]
+        );
+
+        let decoded = decode_score(vec![score(4.0)].encode()[0]);
+        assert!(!decoded.derivative_driven && !decoded.rising);
+    }
+}
@@ -0,0 +1,729 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::f64::consts::PI;
+
+use crate::{
+    coverage::{Coverage, DEFAULT_COVERAGE_RADIUS},
+    solar::Twilight,
+    viewline::EMISSION_HEIGHT,
+};
+
+/// Upper bound on the control points or segments of a curve
+const MAX_CURVE_POINTS: usize = 64;
+/// Upper bound on the degree of the polynomials of a curve
+const MAX_DEGREE: usize = 7;
+
+/// Polynomial applied over `[from, to]`
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PolynomialSegment {
+    pub from: f64,
+    pub to: f64,
+    /// Coefficients by increasing degree
+    pub coefficients: Vec<f64>,
+}
+
+/// Function of a single variable used to score predictions
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Curve {
+    /// Control points `(x, y)` by increasing `x`, interpolated linearly and kept constant past
+    /// both ends, NaN at NaN as the other curves
+    Points(Vec<(f64, f64)>),
+    /// Four parameter logistic `top + (bottom - top) / (1 + (x / inflection)^slope)`
+    Logistic {
+        bottom: f64,
+        top: f64,
+        inflection: f64,
+        slope: f64,
+    },
+    /// Polynomials over ranges of `x`, the first segment holding `x` is used and `otherwise`
+    /// outside of all of them
+    Polynomials {
+        segments: Vec<PolynomialSegment>,
+        otherwise: f64,
+    },
+}
+
+impl Curve {
+    pub fn eval(&self, x: f64) -> f64 {
+        match self {
+            Curve::Points(points) => {
+                let (first, last) = (points[0], points[points.len() - 1]);
+                if x.is_nan() {
+                    return x;
+                }
+                if x <= first.0 {
+                    return first.1;
+                }
+                if x >= last.0 {
+                    return last.1;
+                }
+
+                let idx = points.iter().position(|(px, _)| *px > x).unwrap();
+                let ((x0, y0), (x1, y1)) = (points[idx - 1], points[idx]);
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            }
+            Curve::Logistic {
+                bottom,
+                top,
+                inflection,
+                slope,
+            } => top + (bottom - top) / (1.0 + (x / inflection).powf(*slope)),
+            Curve::Polynomials {
+                segments,
+                otherwise,
+            } => segments
+                .iter()
+                .find(|s| (s.from..=s.to).contains(&x))
+                .map_or(*otherwise, |s| {
+                    s.coefficients
+                        .iter()
+                        .enumerate()
+                        .map(|(degree, c)| c * x.powi(degree as i32))
+                        .sum()
+                }),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Curve::Points(points) => {
+                if points.is_empty() || points.len() > MAX_CURVE_POINTS {
+                    return Err(format!(
+                        "between 1 and {MAX_CURVE_POINTS} control points expected"
+                    ));
+                }
+                if points.iter().any(|(x, y)| !x.is_finite() || !y.is_finite()) {
+                    return Err("control points must be finite".to_string());
+                }
+                if points.windows(2).any(|w| w[0].0 >= w[1].0) {
+                    return Err("control points must be sorted by increasing x".to_string());
+                }
+            }
+            Curve::Logistic {
+                bottom,
+                top,
+                inflection,
+                slope,
+            } => {
+                if [bottom, top, slope].iter().any(|v| !v.is_finite())
+                    || !(inflection.is_finite() && *inflection > 0.0)
+                {
+                    return Err(
+                        "logistic parameters must be finite, inflection positive".to_string()
+                    );
+                }
+            }
+            Curve::Polynomials {
+                segments,
+                otherwise,
+            } => {
+                if segments.len() > MAX_CURVE_POINTS || !otherwise.is_finite() {
+                    return Err(format!(
+                        "at most {MAX_CURVE_POINTS} segments and a finite default expected"
+                    ));
+                }
+                for s in segments {
+                    if !s.from.is_finite() || !s.to.is_finite() || s.from > s.to {
+                        return Err(format!(
+                            "segment [{}, {}]: finite and ordered bounds expected",
+                            s.from, s.to
+                        ));
+                    }
+                    if s.coefficients.len() > MAX_DEGREE + 1 {
+                        return Err(format!(
+                            "segment [{}, {}]: degree at most {MAX_DEGREE} expected",
+                            s.from, s.to
+                        ));
+                    }
+                    if s.coefficients.iter().any(|c| !c.is_finite()) {
+                        return Err(format!(
+                            "segment [{}, {}]: coefficients must be finite",
+                            s.from, s.to
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Displacement of the auroral oval with the activity and the magnetic local time, the
+/// `auroral_zone` curve of a profile giving the weights of the quiet oval
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OvalModel {
+    /// Peak westward electrojet in nT up to which the oval keeps its quiet position
+    pub quiet_electrojet: f64,
+    /// Equatorward expansion of the oval in km per nT of westward electrojet above
+    /// `quiet_electrojet`
+    pub expansion_per_nt: f64,
+    /// Largest equatorward expansion of the oval in km
+    pub max_expansion: f64,
+    /// Equatorward shift of the oval at magnetic midnight in km, the same poleward at noon
+    pub nightside_offset: f64,
+}
+
+impl Default for OvalModel {
+    /// Around 1° of equatorward expansion per 100 nT of westward electrojet, up to the 50°
+    /// geomagnetic latitude reached by the oval during strong storms
+    fn default() -> Self {
+        OvalModel {
+            quiet_electrojet: 200.0,
+            expansion_per_nt: 1.0,
+            max_expansion: 2000.0,
+            nightside_offset: 400.0,
+        }
+    }
+}
+
+impl OvalModel {
+    pub fn validate(&self) -> Result<(), String> {
+        let values = [
+            self.quiet_electrojet,
+            self.expansion_per_nt,
+            self.max_expansion,
+            self.nightside_offset,
+        ];
+        if values.iter().any(|v| !v.is_finite() || *v < 0.0) {
+            return Err("oval parameters must be finite and positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Distance to the geomagnetic pole in km at which the quiet oval stands where the oval at
+    /// the given activity and magnetic local time stands `distance` km from the pole
+    pub fn quiet_distance(&self, distance: f64, mlt: f64, westward_electrojet: f64) -> f64 {
+        let expansion = ((westward_electrojet - self.quiet_electrojet) * self.expansion_per_nt)
+            .clamp(0.0, self.max_expansion);
+        let offset = self.nightside_offset * (2.0 * PI * mlt / 24.0).cos();
+
+        (distance - expansion - offset).max(0.0)
+    }
+}
+
+/// Fading of the scores of the points where the sky is not dark at the time of the prediction
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Darkness {
+    /// Twilight after which scores are kept as they are
+    pub twilight: Twilight,
+    /// Whether scores are dropped before the end of the twilight, rather than scaled with the
+    /// depression of the sun below the horizon
+    pub mask: bool,
+}
+
+impl Darkness {
+    /// Weight in [0, 1] of a score at the given solar zenith angle in degrees
+    pub fn weight(&self, zenith: f64) -> f64 {
+        if self.twilight.ended(zenith) {
+            1.0
+        } else if self.mask {
+            0.0
+        } else {
+            ((zenith - 90.0) / self.twilight.depression()).clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// Scores of the points from which the aurora is seen rather than those it stands above
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LineOfSight {
+    /// Height of the emissions in meters
+    pub emission_altitude: f64,
+    /// Elevation in degrees above which emissions are seen, below they are hidden by the
+    /// landscape and the haze
+    pub min_elevation: f64,
+}
+
+impl Default for LineOfSight {
+    fn default() -> Self {
+        LineOfSight {
+            emission_altitude: EMISSION_HEIGHT,
+            min_elevation: 10.0,
+        }
+    }
+}
+
+impl LineOfSight {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.emission_altitude > 0.0 && self.emission_altitude <= 1000e3) {
+            return Err("emission_altitude must be within ]0, 1000] km".to_string());
+        }
+        if !(0.0..90.0).contains(&self.min_elevation) {
+            return Err("min_elevation must be within [0, 90[".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+/// Fading of the scores of the predictions the observations barely constrain
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct UncertaintyFade {
+    /// Standard deviation in nT of the `i` component at which scores are halved
+    pub half_weight_std: f64,
+}
+
+impl UncertaintyFade {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.half_weight_std.is_finite() && self.half_weight_std > 0.0) {
+            return Err("half_weight_std must be positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Weight in ]0, 1] of a score given the standard deviation of its prediction in nT
+    pub fn weight(&self, std: f64) -> f64 {
+        self.half_weight_std / (self.half_weight_std + std.max(0.0))
+    }
+}
+
+/// Treatment of the scores of the points the stations of the fit do not cover
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CoveragePolicy {
+    /// Radius in km within which stations are counted
+    pub radius: f64,
+    /// Stations needed within the radius for a point to be covered
+    pub min_stations: u32,
+    /// Distance in km to the nearest station beyond which a point is not covered
+    pub max_distance: f64,
+    /// Whether the scores of points that are not covered are dropped, rather than scaled down
+    /// with the distance to the nearest station and the share of the stations missing
+    pub mask: bool,
+}
+
+impl Default for CoveragePolicy {
+    fn default() -> Self {
+        CoveragePolicy {
+            radius: DEFAULT_COVERAGE_RADIUS,
+            min_stations: 1,
+            max_distance: 800.0,
+            mask: false,
+        }
+    }
+}
+
+impl CoveragePolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.radius > 0.0 && self.radius <= 20_000.0) {
+            return Err("radius must be within ]0, 20000] km".to_string());
+        }
+        if !(self.max_distance > 0.0 && self.max_distance.is_finite()) {
+            return Err("max_distance must be positive".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Weight in [0, 1] of a score given the coverage of its point
+    pub fn weight(&self, coverage: &Coverage) -> f64 {
+        let covered =
+            coverage.distance <= self.max_distance && coverage.stations >= self.min_stations;
+        if covered {
+            1.0
+        } else if self.mask {
+            0.0
+        } else {
+            let distance = (self.max_distance / coverage.distance).min(1.0);
+            let stations = if self.min_stations > 0 {
+                (coverage.stations as f64 / self.min_stations as f64).min(1.0)
+            } else {
+                1.0
+            };
+            distance * stations
+        }
+    }
+}
+
+/// Curves turning predicted vectors into scores, selected per deployment
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScoringProfile {
+    /// Score of the absolute value of the `i` component in nT, clamped to [0, 10]
+    pub intensity: Curve,
+    /// Score of the absolute value of the derivative of the `i` component in nT/min
+    pub derivative: Curve,
+    /// Weight in [0, 1] of a score given its distance to the geomagnetic pole in km
+    pub auroral_zone: Curve,
+    /// Displacement of the auroral zone, fixed when `None`
+    pub oval: Option<OvalModel>,
+    /// Fading of the scores in daylight, scores are kept regardless of the sun when `None`
+    pub darkness: Option<Darkness>,
+    /// Scores where the aurora is seen from, where it stands overhead when `None`
+    pub line_of_sight: Option<LineOfSight>,
+    /// Fading of the absolute and derivative scores with the uncertainty of the prediction,
+    /// scores are kept as confident everywhere when `None`
+    pub uncertainty: Option<UncertaintyFade>,
+    /// Treatment of the points the stations do not cover, scores are kept regardless of the
+    /// stations when `None`
+    pub coverage: Option<CoveragePolicy>,
+}
+
+impl Default for ScoringProfile {
+    /// Curves fitted on the historical behaviour of the model:
+    ///
+    /// * intensity, a logistic rising from 0 and giving about 1.4 at 50 nT, 2.5 at 100 nT, 4.2 at
+    ///   200 nT and 10 at 800 nT, before being clamped to [0, 10]
+    /// * derivative, nothing below 10 nT/min then a tenth of it
+    /// * auroral zone, linear between `(0, 0.3)`, `(2200, 1)`, `(2700, 1)`, `(3300, 0.5)`,
+    ///   `(4000, 0.2)` and `(5000, 0)`, the anchors of the legacy cubics, which dropped to 0 at
+    ///   the pole and jumped back to 0.3 past 5000 km, in place regardless of the activity and the
+    ///   magnetic local time unless an `OvalModel` is set
+    fn default() -> Self {
+        ScoringProfile {
+            intensity: Curve::Logistic {
+                bottom: -0.05732817,
+                top: 22.81964,
+                inflection: 1055.17,
+                slope: 0.8849212,
+            },
+            derivative: Curve::Polynomials {
+                segments: vec![PolynomialSegment {
+                    from: 10.0,
+                    to: f64::MAX,
+                    coefficients: vec![0.0, 0.1],
+                }],
+                otherwise: 0.0,
+            },
+            auroral_zone: Curve::Points(vec![
+                (0.0, 0.3),
+                (2200.0, 1.0),
+                (2700.0, 1.0),
+                (3300.0, 0.5),
+                (4000.0, 0.2),
+                (5000.0, 0.0),
+            ]),
+            oval: None,
+            darkness: None,
+            line_of_sight: None,
+            uncertainty: None,
+            coverage: None,
+        }
+    }
+}
+
+impl ScoringProfile {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, curve) in [
+            ("intensity", &self.intensity),
+            ("derivative", &self.derivative),
+            ("auroral_zone", &self.auroral_zone),
+        ] {
+            curve.validate().map_err(|e| format!("{name}: {e}"))?;
+        }
+        if let Some(oval) = &self.oval {
+            oval.validate().map_err(|e| format!("oval: {e}"))?;
+        }
+        if let Some(line_of_sight) = &self.line_of_sight {
+            line_of_sight
+                .validate()
+                .map_err(|e| format!("line_of_sight: {e}"))?;
+        }
+        if let Some(uncertainty) = &self.uncertainty {
+            uncertainty
+                .validate()
+                .map_err(|e| format!("uncertainty: {e}"))?;
+        }
+        if let Some(coverage) = &self.coverage {
+            coverage.validate().map_err(|e| format!("coverage: {e}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Score of the `i` component of a predicted vector in nT
+    pub fn intensity_score(&self, i: f64) -> f64 {
+        self.intensity.eval(i.abs()).clamp(0.0, 10.0)
+    }
+
+    /// Score of the derivative of the `i` component of a predicted vector in nT/min
+    pub fn derivative_score(&self, didt: f64) -> f64 {
+        self.derivative.eval(didt.abs())
+    }
+
+    /// Radius in km within which the stations covering a point are counted
+    pub fn coverage_radius(&self) -> f64 {
+        self.coverage
+            .as_ref()
+            .map_or(DEFAULT_COVERAGE_RADIUS, |c| c.radius)
+    }
+
+    /// Weight of a score given the coverage of its point
+    pub fn coverage_weight(&self, coverage: &Coverage) -> f64 {
+        self.coverage.as_ref().map_or(1.0, |c| c.weight(coverage))
+    }
+
+    /// Weight of a score given the standard deviation of its prediction in nT
+    pub fn uncertainty_weight(&self, std: f64) -> f64 {
+        self.uncertainty.as_ref().map_or(1.0, |u| u.weight(std))
+    }
+
+    /// Weight of a score at the given solar zenith angle in degrees
+    pub fn darkness_weight(&self, zenith: f64) -> f64 {
+        self.darkness.as_ref().map_or(1.0, |d| d.weight(zenith))
+    }
+
+    /// Weight of a score at the given distance to the geomagnetic pole in km, magnetic local
+    /// time and peak westward electrojet in nT
+    pub fn auroral_zone_weight(&self, distance: f64, mlt: f64, westward_electrojet: f64) -> f64 {
+        let distance = match &self.oval {
+            Some(oval) => oval.quiet_distance(distance, mlt, westward_electrojet),
+            None => distance,
+        };
+        self.auroral_zone.eval(distance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Scoring before profiles were introduced
+    fn legacy_intensity(i: f64) -> f64 {
+        let numerator = -0.05732817 - 22.81964;
+        let denominator = 1.0 + (i / 1055.17).powf(0.8849212);
+        (22.81964 + numerator / denominator).clamp(0.0, 10.0)
+    }
+
+    #[test]
+    fn test_default_profile_keeps_legacy_scoring() {
+        let profile = ScoringProfile::default();
+        assert_eq!(profile.validate(), Ok(()));
+
+        for i in [0.0, 10.0, 50.0, 100.0, 200.0, 800.0, 3000.0] {
+            assert_relative_eq!(
+                profile.intensity_score(i),
+                legacy_intensity(i),
+                epsilon = 1e-9
+            );
+            assert_relative_eq!(
+                profile.intensity_score(-i),
+                legacy_intensity(i),
+                epsilon = 1e-9
+            );
+        }
+        for (didt, expected) in [
+            (0.0, 0.0),
+            (9.9, 0.0),
+            (10.0, 1.0),
+            (-35.0, 3.5),
+            (150.0, 15.0),
+        ] {
+            assert_relative_eq!(profile.derivative_score(didt), expected, epsilon = 1e-12);
+        }
+        // as documented on `ScoringProfile::default`
+        for (i, expected) in [
+            (0.0, 0.0),
+            (50.0, 1.4),
+            (100.0, 2.5),
+            (200.0, 4.2),
+            (800.0, 10.0),
+        ] {
+            assert_relative_eq!(profile.intensity_score(i), expected, epsilon = 0.05);
+        }
+        // the auroral zone stays in place at any time and activity
+        for d in (0..6000).step_by(50).map(f64::from) {
+            for (mlt, electrojet) in [(0.0, 0.0), (6.0, 150.0), (12.0, 100.0), (23.0, 1500.0)] {
+                assert_eq!(
+                    profile.auroral_zone_weight(d, mlt, electrojet),
+                    profile.auroral_zone.eval(d)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_auroral_zone() {
+        let zone = ScoringProfile::default().auroral_zone;
+        let weights: Vec<f64> = (0..=7000)
+            .step_by(10)
+            .map(|d| zone.eval(d as f64))
+            .collect();
+
+        // some aurora inside the oval, the most on it and none far from it
+        assert_relative_eq!(weights[0], 0.3);
+        assert_relative_eq!(zone.eval(2500.0), 1.0);
+        assert_relative_eq!(zone.eval(5000.0), 0.0);
+        assert_relative_eq!(zone.eval(9000.0), 0.0);
+        assert!(weights.iter().all(|w| (0.0..=1.0).contains(w)));
+        // continuous, rising towards the oval and falling equatorward of it
+        for (idx, w) in weights.windows(2).enumerate() {
+            assert!((w[1] - w[0]).abs() < 0.01, "{} km", idx * 10);
+            if idx * 10 < 2200 {
+                assert!(w[1] > w[0], "{} km", idx * 10);
+            } else {
+                assert!(w[1] <= w[0], "{} km", idx * 10);
+            }
+        }
+    }
+
+    #[test]
+    fn test_oval_model() {
+        let oval = OvalModel::default();
+        // quiet dawn and dusk ovals stay in place
+        assert_relative_eq!(
+            oval.quiet_distance(2500.0, 6.0, 100.0),
+            2500.0,
+            epsilon = 1e-9
+        );
+        assert_relative_eq!(
+            oval.quiet_distance(2500.0, 18.0, 200.0),
+            2500.0,
+            epsilon = 1e-9
+        );
+        // nightside ovals are further from the pole than dayside ones
+        assert_relative_eq!(
+            oval.quiet_distance(2500.0, 0.0, 0.0),
+            2100.0,
+            epsilon = 1e-9
+        );
+        assert_relative_eq!(
+            oval.quiet_distance(2500.0, 12.0, 0.0),
+            2900.0,
+            epsilon = 1e-9
+        );
+        // storms expand the oval, up to a limit
+        assert_relative_eq!(
+            oval.quiet_distance(3500.0, 6.0, 1200.0),
+            2500.0,
+            epsilon = 1e-9
+        );
+        assert_relative_eq!(
+            oval.quiet_distance(4500.0, 6.0, 5000.0),
+            2500.0,
+            epsilon = 1e-9
+        );
+        assert_relative_eq!(oval.quiet_distance(500.0, 6.0, 5000.0), 0.0);
+
+        // a storm is no longer suppressed at lower latitudes
+        let profile = ScoringProfile {
+            oval: Some(oval),
+            ..ScoringProfile::default()
+        };
+        let quiet = profile.auroral_zone_weight(3800.0, 0.0, 100.0);
+        let storm = profile.auroral_zone_weight(3800.0, 0.0, 1500.0);
+        assert!(quiet < 0.5 && storm > 0.9, "{quiet} {storm}");
+
+        assert!(OvalModel {
+            max_expansion: -1.0,
+            ..OvalModel::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_coverage_policy() {
+        let coverage = |distance: f64, stations: u32| Coverage { distance, stations };
+        let policy = CoveragePolicy {
+            min_stations: 2,
+            ..CoveragePolicy::default()
+        };
+        assert_relative_eq!(policy.weight(&coverage(100.0, 3)), 1.0);
+        assert_relative_eq!(policy.weight(&coverage(1600.0, 2)), 0.5);
+        assert_relative_eq!(policy.weight(&coverage(100.0, 1)), 0.5);
+        assert_relative_eq!(policy.weight(&coverage(f64::INFINITY, 0)), 0.0);
+
+        let mask = CoveragePolicy {
+            mask: true,
+            ..policy
+        };
+        assert_relative_eq!(mask.weight(&coverage(100.0, 2)), 1.0);
+        assert_relative_eq!(mask.weight(&coverage(900.0, 2)), 0.0);
+
+        let profile = ScoringProfile::default();
+        assert_relative_eq!(profile.coverage_weight(&coverage(f64::INFINITY, 0)), 1.0);
+        assert_relative_eq!(profile.coverage_radius(), DEFAULT_COVERAGE_RADIUS);
+    }
+
+    #[test]
+    fn test_uncertainty_fade() {
+        let profile = ScoringProfile {
+            uncertainty: Some(UncertaintyFade {
+                half_weight_std: 50.0,
+            }),
+            ..ScoringProfile::default()
+        };
+        assert_relative_eq!(profile.uncertainty_weight(0.0), 1.0);
+        assert_relative_eq!(profile.uncertainty_weight(50.0), 0.5);
+        assert_relative_eq!(profile.uncertainty_weight(150.0), 0.25);
+        assert_relative_eq!(ScoringProfile::default().uncertainty_weight(150.0), 1.0);
+        assert!(UncertaintyFade {
+            half_weight_std: 0.0
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_darkness() {
+        let scaled = Darkness {
+            twilight: Twilight::Nautical,
+            mask: false,
+        };
+        assert_relative_eq!(scaled.weight(45.0), 0.0);
+        assert_relative_eq!(scaled.weight(93.0), 0.25);
+        assert_relative_eq!(scaled.weight(102.0), 1.0);
+        assert_relative_eq!(scaled.weight(150.0), 1.0);
+
+        let masked = Darkness {
+            twilight: Twilight::Astronomical,
+            mask: true,
+        };
+        assert_relative_eq!(masked.weight(105.0), 0.0);
+        assert_relative_eq!(masked.weight(108.0), 1.0);
+
+        assert_relative_eq!(ScoringProfile::default().darkness_weight(45.0), 1.0);
+    }
+
+    #[test]
+    fn test_points_curve() {
+        let curve = Curve::Points(vec![
+            (0.0, 0.0),
+            (50.0, 2.0),
+            (100.0, 3.0),
+            (200.0, 4.0),
+            (800.0, 10.0),
+        ]);
+        assert_eq!(curve.validate(), Ok(()));
+        assert_relative_eq!(curve.eval(-5.0), 0.0);
+        assert_relative_eq!(curve.eval(25.0), 1.0);
+        assert_relative_eq!(curve.eval(100.0), 3.0);
+        assert_relative_eq!(curve.eval(500.0), 7.0);
+        assert_relative_eq!(curve.eval(1000.0), 10.0);
+        assert!(curve.eval(f64::NAN).is_nan());
+        assert_relative_eq!(curve.eval(f64::INFINITY), 10.0);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Curve::Points(vec![]).validate().is_err());
+        assert!(Curve::Points(vec![(1.0, 0.0), (0.0, 1.0)])
+            .validate()
+            .is_err());
+        assert!(Curve::Points(vec![(0.0, f64::NAN)]).validate().is_err());
+        assert!(Curve::Logistic {
+            bottom: 0.0,
+            top: 10.0,
+            inflection: 0.0,
+            slope: 1.0
+        }
+        .validate()
+        .is_err());
+
+        let profile = ScoringProfile {
+            derivative: Curve::Polynomials {
+                segments: vec![PolynomialSegment {
+                    from: 10.0,
+                    to: 0.0,
+                    coefficients: vec![1.0],
+                }],
+                otherwise: 0.0,
+            },
+            ..ScoringProfile::default()
+        };
+        assert!(profile.validate().unwrap_err().starts_with("derivative"));
+    }
+}
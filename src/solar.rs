@@ -0,0 +1,128 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use std::f64::consts::PI;
+
+use crate::geo::GeographicalPoint;
+
+const NANOS_PER_DAY: f64 = 86_400e9;
+const DAYS_PER_YEAR: f64 = 365.2422;
+/// Tilt of the rotation axis of the Earth in degrees
+const OBLIQUITY: f64 = 23.44;
+
+/// Point where the sun is at the zenith at the given time in nanoseconds since the epoch,
+/// ignoring the equation of time
+pub fn subsolar_point(timestamp: u64) -> GeographicalPoint {
+    let days = timestamp as f64 / NANOS_PER_DAY;
+    let ut_hours = days.fract() * 24.0;
+    // days since the start of the year, the epoch falling on January 1st
+    let day_of_year = days % DAYS_PER_YEAR;
+    // lowest at the winter solstice, 10 days before the start of the year
+    let declination = -OBLIQUITY * (2.0 * PI / DAYS_PER_YEAR * (day_of_year + 10.0)).cos();
+
+    GeographicalPoint::new(
+        declination,
+        (180.0 - 15.0 * ut_hours + 180.0).rem_euclid(360.0) - 180.0,
+    )
+}
+
+/// Angle in degrees between the sun and the zenith at the point, above 90 once the sun set
+pub fn solar_zenith_angle(point: &GeographicalPoint, timestamp: u64) -> f64 {
+    point.angular_distance(&subsolar_point(timestamp))
+}
+
+/// End of the twilight after which the sky is considered dark
+#[derive(CandidType, Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum Twilight {
+    Civil,
+    Nautical,
+    Astronomical,
+}
+
+impl Twilight {
+    /// Angle in degrees of the sun below the horizon at the end of the twilight
+    pub fn depression(&self) -> f64 {
+        match self {
+            Twilight::Civil => 6.0,
+            Twilight::Nautical => 12.0,
+            Twilight::Astronomical => 18.0,
+        }
+    }
+
+    /// Whether the twilight ended at the given solar zenith angle
+    pub fn ended(&self, zenith: f64) -> bool {
+        zenith >= 90.0 + self.depression()
+    }
+}
+
+/// Magnetic local time in hours at the point, `0` on the geomagnetic meridian facing away from
+/// the sun and `12` on the one facing it
+pub fn magnetic_local_time(point: &GeographicalPoint, timestamp: u64) -> f64 {
+    let subsolar = subsolar_point(timestamp);
+    (12.0 + (point.geomagnetic_lon() - subsolar.geomagnetic_lon()) / 15.0).rem_euclid(24.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    const HOUR: u64 = 3_600 * 1_000_000_000;
+    /// 2024-06-20, around the summer solstice
+    const SOLSTICE: u64 = 19_894 * 24 * HOUR;
+
+    #[test]
+    fn test_subsolar_point() {
+        let noon = subsolar_point(SOLSTICE + 12 * HOUR);
+        assert_relative_eq!(noon.lat, OBLIQUITY, epsilon = 0.1);
+        assert_relative_eq!(noon.lon, 0.0, epsilon = 1e-6);
+
+        let evening = subsolar_point(SOLSTICE + 18 * HOUR);
+        assert_relative_eq!(evening.lon, -90.0, epsilon = 1e-6);
+
+        // around the winter solstice
+        let winter = subsolar_point(SOLSTICE + 183 * 24 * HOUR);
+        assert_relative_eq!(winter.lat, -OBLIQUITY, epsilon = 0.2);
+    }
+
+    #[test]
+    fn test_solar_zenith_angle() {
+        let timestamp = SOLSTICE + 12 * HOUR;
+        let subsolar = subsolar_point(timestamp);
+        assert_relative_eq!(
+            solar_zenith_angle(&subsolar, timestamp),
+            0.0,
+            epsilon = 1e-6
+        );
+
+        // midnight sun at the North Cape around local midnight, the sun just above the horizon
+        let north_cape = GeographicalPoint::new(71.17, 25.78);
+        let zenith = solar_zenith_angle(&north_cape, SOLSTICE + 22 * HOUR);
+        assert!((85.0..90.0).contains(&zenith), "{zenith}");
+
+        // polar night at the South Pole
+        let zenith = solar_zenith_angle(&GeographicalPoint::new(-90.0, 0.0), SOLSTICE);
+        assert_relative_eq!(zenith, 90.0 + OBLIQUITY, epsilon = 0.1);
+        assert!(Twilight::Astronomical.ended(zenith));
+        assert!(!Twilight::Nautical.ended(100.0));
+    }
+
+    #[test]
+    fn test_magnetic_local_time() {
+        let timestamp = SOLSTICE + 7 * HOUR;
+        let subsolar = subsolar_point(timestamp);
+        assert_relative_eq!(
+            magnetic_local_time(&subsolar, timestamp),
+            12.0,
+            epsilon = 1e-9
+        );
+
+        let antisolar = GeographicalPoint::new(-subsolar.lat, subsolar.lon + 180.0);
+        let mlt = magnetic_local_time(&antisolar, timestamp);
+        assert!(!(1.0..23.0).contains(&mlt), "{mlt}");
+
+        // Tromsø is around magnetic midnight around 21 UT
+        let tromso = GeographicalPoint::new(69.65, 18.96);
+        let mlt = magnetic_local_time(&tromso, SOLSTICE + 21 * HOUR);
+        assert!(!(1.5..22.5).contains(&mlt), "{mlt}");
+    }
+}
@@ -1,4 +1,4 @@
-use ndarray::{Array, Array2, Axis};
+use ndarray::{Array, Array2};
 
 use crate::geo::GeographicalPoint;
 
@@ -39,8 +39,11 @@ pub fn angular_distance_and_bearing(
             let cos_dlon = dlon.cos();
             let sin_dlon = dlon.sin();
 
-            // Calculate angular distance (theta)
-            theta[[i, j]] = (sin_lat1 * sin_lat2 + cos_lat1 * cos_lat2 * cos_dlon).acos();
+            // Calculate angular distance (theta), rounding errors can push the cosine of
+            // coincident or antipodal points out of [-1, 1]
+            theta[[i, j]] = (sin_lat1 * sin_lat2 + cos_lat1 * cos_lat2 * cos_dlon)
+                .clamp(-1.0, 1.0)
+                .acos();
 
             // Calculate bearing (alpha)
             let x = cos_lat2 * sin_dlon;
@@ -55,6 +58,8 @@ pub fn angular_distance_and_bearing(
 }
 
 #[cfg(test)]
+// expected values are rounded outputs of the reference python implementation
+#[allow(clippy::approx_constant)]
 mod tests {
     use super::*;
     use approx::assert_relative_eq;
@@ -77,6 +82,7 @@ mod tests {
     }
 
     // Helper function to convert nested Vec to Array2
+    #[allow(clippy::ptr_arg)]
     fn vec_to_array2(vec_data: &Vec<Vec<f64>>) -> Array2<f64> {
         let rows = vec_data.len();
         let cols = if rows > 0 { vec_data[0].len() } else { 0 };
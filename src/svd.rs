@@ -61,6 +61,8 @@ pub fn svd(t_obs_flat: &Array2<f64>, epsilon: f64) -> Array2<f64> {
 }
 
 #[cfg(test)]
+// expected values are printed as-is from the reference python implementation
+#[allow(clippy::excessive_precision)]
 mod tests {
     use approx::assert_relative_eq;
 
@@ -1,6 +1,6 @@
 use crate::geo::{GeographicalPoint, R_EARTH};
 use crate::sphere::angular_distance_and_bearing;
-use ndarray::{Array1, Array2, Array3, Zip};
+use ndarray::Array3;
 
 /// Physical constant: permeability of free space (µ0)
 const MU0: f64 = 1e-7;
@@ -48,7 +48,7 @@ pub fn t_df(
     let nsec = secs_locs.len();
     let mut t = Array3::<f64>::zeros((nobs, 3, nsec));
 
-    let (theta, alpha) = angular_distance_and_bearing(&obs_locs, &secs_locs);
+    let (theta, alpha) = angular_distance_and_bearing(obs_locs, secs_locs);
 
     // Pre-compute constants
     let obs_r = obs_altitude + R_EARTH;
@@ -104,6 +104,8 @@ pub fn t_df(
 }
 
 #[cfg(test)]
+// expected values are printed as-is from the reference python implementation
+#[allow(clippy::excessive_precision)]
 mod tests {
     use approx::assert_relative_eq;
 
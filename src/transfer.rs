@@ -0,0 +1,293 @@
+use candid::{CandidType, Deserialize};
+use ndarray::{Array1, Array3, ArrayView1};
+use serde::Serialize;
+
+/// How the prediction transfer matrices are stored.
+///
+/// A dense `f64` matrix takes 8 bytes per entry, 288 MB for the default northern grids (4 810
+/// prediction points, 3 components and 2 500 SEC poles). In single precision it takes 4 bytes per
+/// entry (144 MB), predictions keeping a relative accuracy around `1e-6`. Once pruned only the
+/// entries that were kept are stored, along with the 2 bytes of their column (6 bytes per entry
+/// in single precision, 10 otherwise). The field of a SEC pole fading slowly with the distance,
+/// pruning only pays off over single precision once a third of the entries are dropped: with
+/// 1 000 poles over the northern grid, `3e-2` in single precision keeps 30% of the dense size,
+/// predictions being off by up to 4% of the largest one.
+#[derive(CandidType, Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct TransferStorage {
+    /// Whether entries are stored as `f32` rather than `f64`
+    pub single_precision: bool,
+    /// Entries whose magnitude is below this share of the largest one of their row are dropped,
+    /// the matrix is stored dense when `0`
+    pub prune_below: f64,
+}
+
+impl TransferStorage {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..1.0).contains(&self.prune_below) {
+            return Err("t_pred_storage: prune_below must be within [0, 1[".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Floats {
+    F64(Vec<f64>),
+    F32(Vec<f32>),
+}
+
+impl Floats {
+    fn push(&mut self, value: f64) {
+        match self {
+            Floats::F64(values) => values.push(value),
+            Floats::F32(values) => values.push(value as f32),
+        }
+    }
+
+    fn bytes(&self) -> usize {
+        match self {
+            Floats::F64(values) => values.len() * size_of::<f64>(),
+            Floats::F32(values) => values.len() * size_of::<f32>(),
+        }
+    }
+}
+
+fn dense_dot<T: Copy + Into<f64>>(values: &[T], x: ArrayView1<f64>) -> f64 {
+    values.iter().zip(x).map(|(v, x)| (*v).into() * x).sum()
+}
+
+fn sparse_dot<T: Copy + Into<f64>>(columns: &[u16], values: &[T], x: ArrayView1<f64>) -> f64 {
+    columns
+        .iter()
+        .zip(values)
+        .map(|(col, v)| (*v).into() * x[*col as usize])
+        .sum()
+}
+
+/// Columns and bounds of the rows of a pruned matrix
+#[derive(Debug, Clone, PartialEq)]
+struct SparseRows {
+    /// Start of every row in `columns` and the values, followed by the end of the last one
+    starts: Vec<usize>,
+    columns: Vec<u16>,
+}
+
+/// Prediction transfer matrix, a row of SEC pole coefficients per component of every prediction
+/// point, filled a chunk of prediction points at a time
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransferMatrix {
+    storage: TransferStorage,
+    /// Number of SEC poles, the length of the dense rows
+    secs: usize,
+    rows: usize,
+    values: Floats,
+    /// Entries kept when pruned, `None` when dense
+    sparse: Option<SparseRows>,
+}
+
+impl TransferMatrix {
+    pub fn new(secs: usize, storage: TransferStorage) -> Self {
+        TransferMatrix {
+            storage,
+            secs,
+            rows: 0,
+            values: if storage.single_precision {
+                Floats::F32(vec![])
+            } else {
+                Floats::F64(vec![])
+            },
+            sparse: (storage.prune_below > 0.0).then(|| SparseRows {
+                starts: vec![0],
+                columns: vec![],
+            }),
+        }
+    }
+
+    pub fn storage(&self) -> TransferStorage {
+        self.storage
+    }
+
+    /// Number of prediction points
+    pub fn points(&self) -> usize {
+        self.rows / 3
+    }
+
+    /// Bytes taken by the entries, as reported in the documentation of `TransferStorage`
+    pub fn bytes(&self) -> usize {
+        let columns = self.sparse.as_ref().map_or(0, |s| {
+            s.columns.len() * size_of::<u16>() + s.starts.len() * size_of::<usize>()
+        });
+        self.values.bytes() + columns
+    }
+
+    /// Append the `[point][3][sec]` transfer matrix of the next prediction points
+    pub fn extend(&mut self, t: &Array3<f64>) {
+        assert_eq!(t.shape()[2], self.secs, "Number of SEC poles mismatch");
+        for row in t.rows() {
+            match self.sparse.as_mut() {
+                None => row.iter().for_each(|v| self.values.push(*v)),
+                Some(sparse) => {
+                    let max = row.iter().fold(0.0, |max: f64, v| max.max(v.abs()));
+                    let threshold = self.storage.prune_below * max;
+                    for (col, v) in row.iter().enumerate() {
+                        if v.abs() >= threshold && *v != 0.0 {
+                            sparse.columns.push(col as u16);
+                            self.values.push(*v);
+                        }
+                    }
+                    sparse.starts.push(sparse.columns.len());
+                }
+            }
+            self.rows += 1;
+        }
+    }
+
+    /// Product of the row of the `component` of the prediction `point` with the SEC pole
+    /// coefficients `x`
+    pub fn row_dot(&self, point: usize, component: usize, x: ArrayView1<f64>) -> f64 {
+        let row = 3 * point + component;
+        match &self.sparse {
+            None => {
+                let range = row * self.secs..(row + 1) * self.secs;
+                match &self.values {
+                    Floats::F64(values) => dense_dot(&values[range], x),
+                    Floats::F32(values) => dense_dot(&values[range], x),
+                }
+            }
+            Some(sparse) => {
+                let range = sparse.starts[row]..sparse.starts[row + 1];
+                let columns = &sparse.columns[range.clone()];
+                match &self.values {
+                    Floats::F64(values) => sparse_dot(columns, &values[range], x),
+                    Floats::F32(values) => sparse_dot(columns, &values[range], x),
+                }
+            }
+        }
+    }
+
+    /// Dense copy of the row of the `component` of the prediction `point`
+    pub fn row(&self, point: usize, component: usize) -> Array1<f64> {
+        let row = 3 * point + component;
+        let (range, columns) = match &self.sparse {
+            None => (row * self.secs..(row + 1) * self.secs, None),
+            Some(sparse) => {
+                let range = sparse.starts[row]..sparse.starts[row + 1];
+                (range.clone(), Some(&sparse.columns[range]))
+            }
+        };
+        let values: Vec<f64> = match &self.values {
+            Floats::F64(values) => values[range].to_vec(),
+            Floats::F32(values) => values[range].iter().map(|v| *v as f64).collect(),
+        };
+
+        match columns {
+            None => Array1::from_vec(values),
+            Some(columns) => {
+                let mut dense = Array1::zeros(self.secs);
+                for (col, v) in columns.iter().zip(values) {
+                    dense[*col as usize] = v;
+                }
+                dense
+            }
+        }
+    }
+
+    /// Field predicted at every point from the SEC pole amplitudes
+    pub fn predict(&self, amps: ArrayView1<f64>) -> Vec<[f64; 3]> {
+        (0..self.points())
+            .map(|point| [0, 1, 2].map(|component| self.row_dot(point, component, amps.view())))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{geo::geographical_grid, t_df::t_df};
+    use ndarray::Array1;
+
+    fn transfer_matrix() -> Array3<f64> {
+        let sec_locs = geographical_grid(50.0..80.0, 10, -20.0..40.0, 12);
+        let pred_locs = geographical_grid(45.0..85.0, 9, -40.0..60.0, 11);
+        t_df(&pred_locs, 110e3, &sec_locs, 110e3)
+    }
+
+    #[test]
+    fn test_dense_matrix_is_exact() {
+        let t = transfer_matrix();
+        let mut matrix = TransferMatrix::new(120, TransferStorage::default());
+        // in two chunks
+        let half = t.shape()[0] / 2;
+        matrix.extend(&t.slice(ndarray::s![..half, .., ..]).to_owned());
+        matrix.extend(&t.slice(ndarray::s![half.., .., ..]).to_owned());
+
+        assert_eq!(matrix.points(), t.shape()[0]);
+        assert_eq!(matrix.bytes(), t.len() * 8);
+        assert_eq!(matrix.row(17, 2), t.slice(ndarray::s![17, 2, ..]));
+    }
+
+    /// Size of the matrix stored as `storage` relative to its dense `f64` size, and largest error
+    /// of its predictions relative to the largest predicted component
+    fn compare(t: &Array3<f64>, storage: TransferStorage) -> (f64, f64) {
+        let secs = t.shape()[2];
+        let amps = Array1::from_iter((0..secs).map(|i| ((i * 37 % 17) as f64 - 8.0) * 1e4));
+        let mut dense = TransferMatrix::new(secs, TransferStorage::default());
+        dense.extend(t);
+        let mut compact = TransferMatrix::new(secs, storage);
+        compact.extend(t);
+
+        let expected = dense.predict(amps.view());
+        let actual = compact.predict(amps.view());
+        let scale = expected
+            .iter()
+            .flatten()
+            .fold(0.0, |m: f64, v| m.max(v.abs()));
+        let error = actual
+            .iter()
+            .flatten()
+            .zip(expected.iter().flatten())
+            .fold(0.0, |m: f64, (a, e)| m.max((a - e).abs()));
+        (compact.bytes() as f64 / dense.bytes() as f64, error / scale)
+    }
+
+    #[test]
+    fn test_compact_storage_accuracy() {
+        let t = transfer_matrix();
+        let storage = |single_precision, prune_below| TransferStorage {
+            single_precision,
+            prune_below,
+        };
+
+        let (size, error) = compare(&t, storage(true, 0.0));
+        assert_eq!(size, 0.5);
+        assert!(error < 1e-6, "{error}");
+        // the poles of this grid are close enough to the predicted points to prune a lot
+        let (size, error) = compare(&t, storage(false, 0.1));
+        assert!(size < 1.0 && error < 1e-5, "{size} {error}");
+        // pruned in single precision, smaller than dense in single precision
+        let (size, error) = compare(&t, storage(true, 0.1));
+        assert!(size < 0.5 && error < 1e-5, "{size} {error}");
+
+        assert!(storage(false, 1.0).validate().is_err());
+        assert!(storage(false, -0.1).validate().is_err());
+    }
+
+    #[test]
+    fn test_documented_pruning() {
+        // 1 000 poles over the northern grid, as in the documentation of `TransferStorage`
+        let sec_locs = geographical_grid(45.0..85.0, 25, -170.0..35.0, 40);
+        let pred_locs = geographical_grid(45.0..85.0, 10, -170.0..35.0, 20);
+        let t = t_df(&pred_locs, 110e3, &sec_locs, 110e3);
+
+        let (size, error) = compare(
+            &t,
+            TransferStorage {
+                single_precision: true,
+                prune_below: 3e-2,
+            },
+        );
+        assert!(size <= 0.31, "{size}");
+        assert!(error <= 0.04, "{error}");
+    }
+}
@@ -0,0 +1,236 @@
+use candid::{CandidType, Deserialize};
+
+use crate::{
+    format::ScoresContainer,
+    geo::{GeographicalPoint, GridDefinition, Hemisphere, R_EARTH},
+    overlays::ScoreVector,
+};
+
+/// Height of the lower border of the auroral emissions in meters
+pub const EMISSION_HEIGHT: f64 = 110e3;
+
+/// Where the aurora can be seen from, for a hemisphere
+#[derive(CandidType, Deserialize, Debug, Clone, PartialEq)]
+pub struct ViewLine {
+    pub hemisphere: Hemisphere,
+    pub threshold: f64,
+    /// Most equatorward points where the score reaches the threshold, split where no longitude
+    /// of the grid does
+    pub overhead: Vec<Vec<GeographicalPoint>>,
+    /// `overhead` moved equatorward by the distance from which the emissions stand on the horizon
+    pub horizon: Vec<Vec<GeographicalPoint>>,
+}
+
+/// Angular distance in degrees from which an emission at `height` meters stands on the horizon
+pub fn horizon_distance(height: f64) -> f64 {
+    (R_EARTH / (R_EARTH + height)).acos().to_degrees()
+}
+
+/// Most equatorward latitude of each longitude of the grid where the score reaches `threshold`,
+/// interpolated between the rows of the grid. `None` for longitudes where it never does.
+pub fn equatorward_boundary(
+    grid: &GridDefinition,
+    hemisphere: Hemisphere,
+    scores: &[ScoreVector],
+    threshold: f64,
+) -> Vec<Option<GeographicalPoint>> {
+    let (lat_steps, lon_steps) = (grid.lat_steps as usize, grid.lon_steps as usize);
+    if scores.len() != lat_steps * lon_steps {
+        return vec![None; lon_steps];
+    }
+    // rows ordered from the equator to the pole
+    let rows: Vec<usize> = match hemisphere {
+        Hemisphere::North => (0..lat_steps).collect(),
+        Hemisphere::South => (0..lat_steps).rev().collect(),
+    };
+
+    (0..lon_steps)
+        .map(|j| {
+            let at = |i: usize| &scores[i * lon_steps + j];
+            let n = rows.iter().position(|i| at(*i).score >= threshold)?;
+            let inside = at(rows[n]);
+            if n == 0 {
+                return Some(GeographicalPoint::new(inside.lat, inside.lon));
+            }
+
+            let outside = at(rows[n - 1]);
+            let t = (threshold - outside.score) / (inside.score - outside.score);
+            Some(GeographicalPoint::new(
+                outside.lat + t * (inside.lat - outside.lat),
+                inside.lon,
+            ))
+        })
+        .collect()
+}
+
+/// Splits the points into polylines at the missing ones, isolated points are dropped
+fn polylines(points: Vec<Option<GeographicalPoint>>) -> Vec<Vec<GeographicalPoint>> {
+    points
+        .split(|p| p.is_none())
+        .filter(|line| line.len() > 1)
+        .map(|line| line.iter().flatten().copied().collect())
+        .collect()
+}
+
+pub fn view_line(
+    grid: &GridDefinition,
+    hemisphere: Hemisphere,
+    scores: &[ScoreVector],
+    threshold: f64,
+) -> ViewLine {
+    let overhead = polylines(equatorward_boundary(grid, hemisphere, scores, threshold));
+    let shift = match hemisphere {
+        Hemisphere::North => -horizon_distance(EMISSION_HEIGHT),
+        Hemisphere::South => horizon_distance(EMISSION_HEIGHT),
+    };
+    let horizon = overhead
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|p| GeographicalPoint::new((p.lat + shift).clamp(-90.0, 90.0), p.lon))
+                .collect()
+        })
+        .collect();
+
+    ViewLine {
+        hemisphere,
+        threshold,
+        overhead,
+        horizon,
+    }
+}
+
+/// View lines of every section of the container, from the scores as they are served
+pub fn view_lines(container: &ScoresContainer, threshold: f64) -> Vec<ViewLine> {
+    container
+        .sections
+        .iter()
+        .map(|section| {
+            view_line(
+                &section.grid,
+                section.hemisphere,
+                &section.score_vectors(),
+                threshold,
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format::ScoresSection, overlays::encode_score};
+    use approx::assert_relative_eq;
+
+    fn grid_between(lat_start: f64, lat_end: f64) -> GridDefinition {
+        GridDefinition {
+            lat_start,
+            lat_end,
+            lat_steps: 5,
+            lon_start: 0.0,
+            lon_end: 40.0,
+            lon_steps: 5,
+        }
+    }
+
+    /// Scores growing by 1 per row towards the pole, none in the last column
+    fn scores(grid: &GridDefinition, hemisphere: Hemisphere) -> Vec<ScoreVector> {
+        grid.points()
+            .iter()
+            .enumerate()
+            .map(|(idx, p)| {
+                let row = idx / grid.lon_steps as usize;
+                let row = match hemisphere {
+                    Hemisphere::North => row,
+                    Hemisphere::South => grid.lat_steps as usize - 1 - row,
+                };
+                let score = if idx % 5 == 4 { 0.0 } else { row as f64 };
+                ScoreVector {
+                    lat: p.lat,
+                    lon: p.lon,
+                    score,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_horizon_distance() {
+        // around 1 170 km at 110 km
+        assert_relative_eq!(horizon_distance(110e3), 10.5, epsilon = 0.1);
+        assert_relative_eq!(horizon_distance(0.0), 0.0);
+    }
+
+    #[test]
+    fn test_equatorward_boundary() {
+        let grid = grid_between(50.0, 70.0);
+        let boundary = equatorward_boundary(
+            &grid,
+            Hemisphere::North,
+            &scores(&grid, Hemisphere::North),
+            1.5,
+        );
+        assert_relative_eq!(boundary[0].unwrap().lat, 57.5);
+        assert_relative_eq!(boundary[3].unwrap().lon, 30.0);
+        assert!(boundary[4].is_none());
+
+        let south = grid_between(-70.0, -50.0);
+        let boundary = equatorward_boundary(
+            &south,
+            Hemisphere::South,
+            &scores(&south, Hemisphere::South),
+            1.5,
+        );
+        assert_relative_eq!(boundary[0].unwrap().lat, -57.5);
+    }
+
+    #[test]
+    fn test_view_line() {
+        let grid = grid_between(50.0, 70.0);
+        let line = view_line(
+            &grid,
+            Hemisphere::North,
+            &scores(&grid, Hemisphere::North),
+            3.0,
+        );
+        assert_eq!(line.overhead.len(), 1);
+        assert_eq!(line.overhead[0].len(), 4);
+        assert_relative_eq!(line.overhead[0][0].lat, 65.0);
+        assert_relative_eq!(
+            line.horizon[0][0].lat,
+            65.0 - horizon_distance(EMISSION_HEIGHT)
+        );
+
+        let quiet = view_line(
+            &grid,
+            Hemisphere::North,
+            &scores(&grid, Hemisphere::North),
+            9.0,
+        );
+        assert!(quiet.overhead.is_empty() && quiet.horizon.is_empty());
+    }
+
+    #[test]
+    fn test_view_lines_of_container() {
+        let grid = grid_between(-70.0, -50.0);
+        let scores = scores(&grid, Hemisphere::South)
+            .iter()
+            .map(|s| encode_score(s.score, 0))
+            .collect();
+        let container = ScoresContainer::new(
+            0,
+            false,
+            vec![ScoresSection {
+                hemisphere: Hemisphere::South,
+                grid,
+                scores,
+            }],
+        );
+
+        let lines = view_lines(&container, 2.0);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].hemisphere, Hemisphere::South);
+        assert_relative_eq!(lines[0].overhead[0][0].lat, -60.0);
+        assert!(lines[0].horizon[0][0].lat > -60.0);
+    }
+}